@@ -0,0 +1,27 @@
+use std::time::Instant;
+
+use dotenvy::var;
+use rautey::{middleware::Next, request::Request, response::Response, server::Server};
+
+fn main() {
+    let mut server = Server::new(var("APP_PORT").unwrap());
+    server.router.register_around_middleware(
+        "timing",
+        |req: Request, res: &mut Response, next: Next| {
+            let started = Instant::now();
+            next.run(req, res);
+            res.with_header(
+                "X-Response-Time",
+                format!("{}us", started.elapsed().as_micros()),
+            );
+        },
+    );
+
+    server
+        .router
+        .get("/", |_: Request, res: &mut Response| {
+            res.text("Check the X-Response-Time header");
+        })
+        .with_middlewares(["timing"]);
+    server.listen().expect("Could not bind port");
+}
@@ -0,0 +1,33 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use dotenvy::var;
+use rautey::{middleware::Flow, request::Request, response::Response, server::Server};
+
+fn main() {
+    let mut server = Server::new(var("APP_PORT").unwrap());
+    let visits = Arc::new(AtomicUsize::new(0));
+    let greeting = String::from("Welcome back");
+
+    let counter = Arc::clone(&visits);
+    server
+        .router
+        .register_middleware("count-visits", move |_: &Request, _: &mut Response| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Flow::Continue
+        });
+
+    server
+        .router
+        .get("/", move |_: Request, res: &mut Response| {
+            res.text(format!(
+                "{}, visitor #{}",
+                greeting,
+                visits.load(Ordering::SeqCst)
+            ));
+        })
+        .with_middlewares(["count-visits"]);
+    server.listen().expect("Could not bind port");
+}
@@ -3,7 +3,7 @@ use rautey::{cookie::Cookie, request::Request, response::Response, server::Serve
 
 fn main() {
     let mut server = Server::new(var("APP_PORT").unwrap());
-    server.router.get("/", |req: Request, mut r: Response| {
+    server.router.get("/", |req: Request, r: &mut Response| {
         println!("{:?}", req.cookies); //cookies in request
 
         // add cookies to response
@@ -0,0 +1,46 @@
+use dotenvy::var;
+use rautey::{
+    extract::{Path, Query},
+    response::{IntoResponse, Json, Response},
+    server::Server,
+    session::{SessionBackend, SessionStore},
+    HTTPStatus,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize)]
+struct Filters {
+    page: Option<u32>,
+    #[serde(default)]
+    search: String,
+}
+
+#[derive(Deserialize, Serialize)]
+struct NewUser {
+    name: String,
+}
+
+fn list_posts(Path(user_id): Path<u64>, Query(filters): Query<Filters>) -> String {
+    format!(
+        "Posts of user {} matching {:?}, page {}",
+        user_id,
+        filters.search,
+        filters.page.unwrap_or(1)
+    )
+}
+
+fn create_user(
+    Json(user): Json<NewUser>,
+    mut session: SessionBackend,
+    res: &mut Response,
+) -> impl IntoResponse {
+    session.set("last_created", &user.name, res);
+    (HTTPStatus::CREATED, Json(user))
+}
+
+fn main() {
+    let mut server = Server::new(var("APP_PORT").unwrap());
+    server.router.get("/users/{id:int}/posts", list_posts);
+    server.router.post("/users", create_user);
+    server.listen().expect("Could not bind port");
+}
@@ -3,7 +3,7 @@ use rautey::{request::Request, response::Response, server::Server};
 
 fn main() {
     let mut server = Server::new(var("APP_PORT").unwrap());
-    server.router.get("/", |_: Request, mut res: Response| {
+    server.router.get("/", |_: Request, res: &mut Response| {
         res.text("Hello World");
     });
     server.listen().expect("Could not bind port");
@@ -1,5 +1,5 @@
 use dotenvy::var;
-use rautey::{request::Request, response::Response, server::Server};
+use rautey::{middleware::Flow, request::Request, response::Response, server::Server};
 
 fn main() {
     let mut server = Server::new(var("APP_PORT").unwrap());
@@ -9,7 +9,9 @@ fn main() {
             if !req.cookies.contains_key("auth_token") {
                 res.with_status(rautey::HTTPStatus::UNAUTHORIZED)
                     .text("Permission denied");
+                return Flow::Stop;
             }
+            Flow::Continue
         });
 
     server
@@ -19,7 +21,7 @@ fn main() {
     server.listen().expect("Could not bind port");
 }
 
-fn get_user_details(req: Request, mut r: Response) {
+fn get_user_details(req: Request, r: &mut Response) {
     r.text(format!(
         "User id is {}",
         req.route_params.get("id").unwrap()
@@ -0,0 +1,38 @@
+use dotenvy::var;
+use rautey::{
+    error::HttpError,
+    request::Request,
+    response::{Html, Json, Redirect},
+    server::Server,
+    HTTPStatus,
+};
+use serde::Serialize;
+
+#[derive(Serialize)]
+struct User {
+    id: u64,
+    name: String,
+}
+
+fn show_user(req: Request) -> Result<Json<User>, HttpError> {
+    let id = req.param::<u64>("id")?;
+    Ok(Json(User {
+        id,
+        name: format!("User {}", id),
+    }))
+}
+
+fn main() {
+    let mut server = Server::new(var("APP_PORT").unwrap());
+    server
+        .router
+        .get("/", |_: Request| Html("<h1>Hello World</h1>"));
+    server.router.get("/users/{id}", show_user);
+    server
+        .router
+        .post("/users", |_: Request| (HTTPStatus::CREATED, "Created"));
+    server
+        .router
+        .get("/home", |_: Request| Redirect::permanent("/"));
+    server.listen().expect("Could not bind port");
+}
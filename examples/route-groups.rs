@@ -12,10 +12,10 @@ fn main() {
     server.listen().expect("Could not bind port");
 }
 
-fn handle_api_home(_: Request, mut r: Response) {
+fn handle_api_home(_: Request, r: &mut Response) {
     r.text("This is api home");
 }
 
-fn handle_v2_home(_: Request, mut res: Response) {
+fn handle_v2_home(_: Request, res: &mut Response) {
     res.text("This is v2 home");
 }
@@ -1,15 +1,39 @@
 use dotenvy::var;
-use rautey::{request::Request, response::Response, server::Server};
+use rautey::{request::Request, response::Response, server::Server, HTTPStatus};
 
 fn main() {
     let mut server = Server::new(var("APP_PORT").unwrap());
     server
         .router
-        .get("/users/{id}", |req: Request, mut r: Response| {
+        .get("/users/{id}", |req: Request, r: &mut Response| {
             r.text(format!(
                 "User id is {}",
                 req.route_params.get("id").unwrap()
             ));
         });
+    server
+        .router
+        .get("/posts/{id:int}", |req: Request, r: &mut Response| {
+            match req.param::<u64>("id") {
+                Ok(id) => r.text(format!("Post id is {}", id)),
+                Err(e) => r.with_status(HTTPStatus::BAD_REQUEST).text(e.to_string()),
+            };
+        });
+    server
+        .router
+        .get("/tags/{slug:[a-z-]+}", |req: Request, r: &mut Response| {
+            r.text(format!("Tag is {}", req.route_params["slug"]));
+        });
+    server
+        .router
+        .get("/files/{*path}", |req: Request, r: &mut Response| {
+            r.text(format!("Browsing /{}", req.route_params["path"]));
+        });
+    server
+        .router
+        .get("/pages/{page?:int}", |req: Request, r: &mut Response| {
+            let page = req.param::<u32>("page").unwrap_or(1);
+            r.text(format!("Page {}", page));
+        });
     server.listen().expect("Could not bind port");
 }
@@ -0,0 +1,32 @@
+use std::{thread, time::Duration};
+
+use dotenvy::var;
+use rautey::{request::Request, response::Response, server::Server, sse::Event};
+
+fn main() {
+    let mut server = Server::new(var("APP_PORT").unwrap());
+    server
+        .router
+        .get("/events", |req: Request, res: &mut Response| {
+            // resume counting from the last event the client saw
+            let start: u64 = req
+                .last_event_id()
+                .and_then(|id| id.parse().ok())
+                .map_or(0, |id: u64| id + 1);
+
+            let mut events = res.sse().unwrap();
+            for tick in start..start + 10 {
+                let event = Event::new(format!("tick {}", tick))
+                    .with_id(tick.to_string())
+                    .with_event("tick")
+                    .with_retry(1000);
+                if events.send(event).is_err() {
+                    // client went away
+                    return;
+                }
+                thread::sleep(Duration::from_secs(1));
+            }
+            events.close().unwrap();
+        });
+    server.listen().expect("Could not bind port");
+}
@@ -5,14 +5,14 @@ fn main() {
     let mut server = Server::new(var("APP_PORT").unwrap());
     server
         .router
-        .get("/", |mut req: Request, mut res: Response| {
+        .get("/", |mut req: Request, res: &mut Response| {
             // access request sesion
             println!(
                 "{:?}",
                 req.session.get::<String>("session_id").unwrap_or_default()
             );
 
-            req.session.set("session_id", "1234", &mut res);
+            req.session.set("session_id", "1234", res);
             res.text("Session example");
         });
     server.listen().expect("Could not bind port");
@@ -0,0 +1,25 @@
+use dotenvy::var;
+use rautey::{request::Request, response::Response, server::Server};
+
+struct AppState {
+    app_name: String,
+    support_email: String,
+}
+
+fn main() {
+    let mut server = Server::new(var("APP_PORT").unwrap());
+    server.with_state(AppState {
+        app_name: "Rautey".to_string(),
+        support_email: "support@example.com".to_string(),
+    });
+    server.router.get("/", handle_home);
+    server.listen().expect("Could not bind port");
+}
+
+fn handle_home(req: Request, res: &mut Response) {
+    let state = req.state::<AppState>().unwrap();
+    res.text(format!(
+        "Welcome to {}, contact {} for help",
+        state.app_name, state.support_email
+    ));
+}
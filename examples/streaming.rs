@@ -0,0 +1,25 @@
+use std::io::Write;
+
+use dotenvy::var;
+use rautey::{request::Request, response::Response, server::Server};
+
+fn main() {
+    let mut server = Server::new(var("APP_PORT").unwrap());
+    server
+        .router
+        .get("/report.csv", |_: Request, res: &mut Response| {
+            let mut writer = res.stream("text/csv").unwrap();
+            writeln!(writer, "id,name").unwrap();
+            for id in 1..=1000 {
+                writeln!(writer, "{},user-{}", id, id).unwrap();
+            }
+            writer.finish().unwrap();
+        });
+    server
+        .router
+        .get("/numbers", |_: Request, res: &mut Response| {
+            let numbers = (1..=10).map(|n| format!("{}\n", n));
+            res.stream_chunks("text/plain", numbers).unwrap();
+        });
+    server.listen().expect("Could not bind port");
+}
@@ -0,0 +1,23 @@
+use dotenvy::var;
+use rautey::{
+    request::Request,
+    server::Server,
+    websocket::{Message, WebSocket},
+};
+
+fn main() {
+    let mut server = Server::new(var("APP_PORT").unwrap());
+    server
+        .router
+        .websocket("/echo", |_: Request, mut socket: WebSocket| {
+            while let Ok(message) = socket.receive() {
+                match message {
+                    Message::Text(text) => socket.send_text(format!("echo: {}", text)).unwrap(),
+                    Message::Binary(data) => socket.send_binary(data).unwrap(),
+                    Message::Close(_) => break,
+                    _ => {}
+                }
+            }
+        });
+    server.listen().expect("Could not bind port");
+}
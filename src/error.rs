@@ -0,0 +1,143 @@
+use std::{error::Error, fmt::Display, io};
+
+use super::{logging::log, request::ParamError, response::Response, url::UrlError, HTTPStatus};
+
+/// An error answered to the client instead of a route handler's response, such as
+/// a missing route, a method the route doesn't handle or an error returned by a
+/// handler. It is rendered by the handler registered with `Router::on_error`, or
+/// sent as plain text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpError {
+    pub status: HTTPStatus,
+    pub message: String,
+}
+
+impl HttpError {
+    pub fn new<T: Into<String>>(status: HTTPStatus, message: T) -> HttpError {
+        HttpError {
+            status,
+            message: message.into(),
+        }
+    }
+}
+
+impl Display for HttpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.status.status_code(), self.message)
+    }
+}
+
+impl std::error::Error for HttpError {}
+
+impl From<HTTPStatus> for HttpError {
+    /// An error with the reason phrase of `status` as message.
+    fn from(status: HTTPStatus) -> Self {
+        let message = status.status_text().to_owned();
+        HttpError::new(status, message)
+    }
+}
+
+impl From<ParamError> for HttpError {
+    fn from(e: ParamError) -> Self {
+        match e {
+            ParamError::Missing(_) => e.status().into(),
+            ParamError::Invalid { .. } => HttpError::new(e.status(), e.to_string()),
+        }
+    }
+}
+
+impl From<UrlError> for HttpError {
+    fn from(e: UrlError) -> Self {
+        e.status().into()
+    }
+}
+
+/// An error a handler can return, turning into the response sent to the client.
+pub trait ResponseError: Display {
+    fn status(&self) -> HTTPStatus {
+        HTTPStatus::INTERNAL_SERVER_ERROR
+    }
+
+    /// Writes the error to the response. By default, an [`HttpError`] with the
+    /// status and message of the error is rendered by the router's error handler.
+    fn error_response(&self, response: &mut Response) {
+        response.error(HttpError::new(self.status(), self.to_string()));
+    }
+}
+
+/// Writes an error from a handler or an extractor to the response, logging server
+/// errors first, as the response usually leaves their cause out.
+pub(crate) fn respond_with<E: ResponseError + ?Sized>(error: &E, response: &mut Response) {
+    if error.status().status_code() >= 500 {
+        let _ = log(format!("Error while handling request: {}", error));
+    }
+    error.error_response(response);
+}
+
+impl ResponseError for HttpError {
+    fn status(&self) -> HTTPStatus {
+        self.status.clone()
+    }
+
+    fn error_response(&self, response: &mut Response) {
+        response.error(self.clone());
+    }
+}
+
+impl ResponseError for serde_json::Error {
+    /// Malformed JSON is a bad request, while JSON that doesn't fit the expected
+    /// type is unprocessable.
+    fn status(&self) -> HTTPStatus {
+        match self.classify() {
+            serde_json::error::Category::Syntax | serde_json::error::Category::Eof => {
+                HTTPStatus::BAD_REQUEST
+            }
+            serde_json::error::Category::Data => HTTPStatus::UNPROCESSABLE_ENTITY,
+            serde_json::error::Category::Io => HTTPStatus::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl ResponseError for io::Error {
+    fn status(&self) -> HTTPStatus {
+        match self.kind() {
+            io::ErrorKind::NotFound => HTTPStatus::NOT_FOUND,
+            io::ErrorKind::PermissionDenied => HTTPStatus::FORBIDDEN,
+            _ => HTTPStatus::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Answers with the reason phrase of the status, as IO errors describe the server.
+    fn error_response(&self, response: &mut Response) {
+        response.error(self.status());
+    }
+}
+
+impl ResponseError for Box<dyn Error + Send + Sync> {
+    fn error_response(&self, response: &mut Response) {
+        response.error(self.status());
+    }
+}
+
+impl ResponseError for ParamError {
+    /// A missing param is a mistake of the application, as the route pattern
+    /// decides which params there are.
+    fn status(&self) -> HTTPStatus {
+        match self {
+            ParamError::Missing(_) => HTTPStatus::INTERNAL_SERVER_ERROR,
+            ParamError::Invalid { .. } => HTTPStatus::BAD_REQUEST,
+        }
+    }
+
+    fn error_response(&self, response: &mut Response) {
+        response.error(HttpError::from(self.clone()));
+    }
+}
+
+impl ResponseError for UrlError {
+    /// Answers with the reason phrase of the status, as a URL that can't be
+    /// generated is a mistake of the application.
+    fn error_response(&self, response: &mut Response) {
+        response.error(self.status());
+    }
+}
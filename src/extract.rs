@@ -0,0 +1,431 @@
+use std::collections::HashMap;
+
+use serde::{
+    de::{
+        self,
+        value::{Error as ValueError, MapDeserializer, StrDeserializer},
+        DeserializeOwned, IntoDeserializer, Visitor,
+    },
+    forward_to_deserialize_any, Deserializer,
+};
+use urlencoding::decode;
+
+use super::{
+    error::{HttpError, ResponseError},
+    request::Request,
+    response::Json,
+    session::{NoSession, SessionBackend},
+    HTTPStatus,
+};
+
+/// A handler argument built from parts of the request, leaving the rest for the
+/// following arguments. When extraction fails, the error is answered instead of
+/// running the handler.
+pub trait FromRequestParts: Sized {
+    fn from_request_parts(request: &mut Request) -> Result<Self, HttpError>;
+}
+
+/// A handler argument consuming the whole request, such as `Request` itself.
+/// Only the last argument of a handler can be one.
+pub trait FromRequest: Sized {
+    fn from_request(request: Request) -> Result<Self, HttpError>;
+}
+
+/// The route params, deserialized into a struct with a field per param, a map,
+/// or a single value when the route has one param.
+#[derive(Debug, Clone)]
+pub struct Path<T>(pub T);
+
+/// The query string, deserialized into a struct with a field per key, or a map.
+#[derive(Debug, Clone)]
+pub struct Query<T>(pub T);
+
+/// The request headers.
+#[derive(Debug, Clone)]
+pub struct Headers(pub HashMap<String, String>);
+
+/// The cookies sent with the request.
+#[derive(Debug, Clone)]
+pub struct Cookies(pub HashMap<String, String>);
+
+impl FromRequest for Request {
+    fn from_request(request: Request) -> Result<Self, HttpError> {
+        Ok(request)
+    }
+}
+
+impl<T: DeserializeOwned> FromRequestParts for Path<T> {
+    fn from_request_parts(request: &mut Request) -> Result<Self, HttpError> {
+        T::deserialize(Params::new(&request.route_params))
+            .map(Path)
+            .map_err(|e| {
+                HttpError::new(
+                    HTTPStatus::BAD_REQUEST,
+                    format!("Invalid route params: {}", e),
+                )
+            })
+    }
+}
+
+impl<T: DeserializeOwned> FromRequestParts for Query<T> {
+    fn from_request_parts(request: &mut Request) -> Result<Self, HttpError> {
+        let decode = |value: &str| {
+            let value = value.replace('+', " ");
+            decode(&value).map(|v| v.into_owned()).unwrap_or(value)
+        };
+        let query: HashMap<String, String> = request
+            .query
+            .iter()
+            .map(|(k, v)| (decode(k), decode(v)))
+            .collect();
+        T::deserialize(Params::new(&query)).map(Query).map_err(|e| {
+            HttpError::new(
+                HTTPStatus::BAD_REQUEST,
+                format!("Invalid query string: {}", e),
+            )
+        })
+    }
+}
+
+/// Malformed JSON is answered with `400 Bad Request`, JSON that doesn't fit `T`
+/// with `422 Unprocessable Entity`, and other content types with `415 Unsupported Media Type`.
+impl<T: DeserializeOwned> FromRequestParts for Json<T> {
+    fn from_request_parts(request: &mut Request) -> Result<Self, HttpError> {
+        let is_json = request
+            .header("Content-Type")
+            .is_some_and(|content_type| content_type.trim().starts_with("application/json"));
+        if !is_json {
+            return Err(HttpError::new(
+                HTTPStatus::UNSUPPORTED_MEDIA_TYPE,
+                "Expected a JSON body",
+            ));
+        }
+        serde_json::from_slice(&request.body)
+            .map(Json)
+            .map_err(|e| HttpError::new(e.status(), e.to_string()))
+    }
+}
+
+impl FromRequestParts for SessionBackend {
+    fn from_request_parts(request: &mut Request) -> Result<Self, HttpError> {
+        Ok(std::mem::replace(
+            &mut request.session,
+            SessionBackend::NoSession(NoSession::new()),
+        ))
+    }
+}
+
+impl FromRequestParts for Headers {
+    fn from_request_parts(request: &mut Request) -> Result<Self, HttpError> {
+        Ok(Headers(request.headers.clone()))
+    }
+}
+
+impl FromRequestParts for Cookies {
+    fn from_request_parts(request: &mut Request) -> Result<Self, HttpError> {
+        Ok(Cookies(request.cookies.clone()))
+    }
+}
+
+/// `None` when the extraction fails, instead of answering the error.
+impl<T: FromRequestParts> FromRequestParts for Option<T> {
+    fn from_request_parts(request: &mut Request) -> Result<Self, HttpError> {
+        Ok(T::from_request_parts(request).ok())
+    }
+}
+
+/// Deserializes string key-value pairs, such as route params or a query string,
+/// parsing the values into the types the fields expect.
+struct Params<'a> {
+    values: &'a HashMap<String, String>,
+}
+
+impl<'a> Params<'a> {
+    fn new(values: &'a HashMap<String, String>) -> Params<'a> {
+        Params { values }
+    }
+
+    /// The only value, for types deserialized from a single param.
+    fn single(&self) -> Result<Value<'a>, ValueError> {
+        let mut values = self.values.values();
+        match (values.next(), values.next()) {
+            (Some(value), None) => Ok(Value(value)),
+            _ => Err(de::Error::custom(format!(
+                "expected a single value, found {}",
+                self.values.len()
+            ))),
+        }
+    }
+}
+
+macro_rules! forward_to_single {
+    ($($method:ident)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+                self.single()?.$method(visitor)
+            }
+        )*
+    };
+}
+
+impl<'de> Deserializer<'de> for Params<'_> {
+    type Error = ValueError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        let entries = self
+            .values
+            .iter()
+            .map(|(key, value)| (key.as_str(), Value(value)));
+        visitor.visit_map(MapDeserializer::new(entries))
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.single()?.deserialize_enum(name, variants, visitor)
+    }
+
+    forward_to_single! {
+        deserialize_bool deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64
+        deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64 deserialize_f32
+        deserialize_f64 deserialize_char deserialize_str deserialize_string
+    }
+
+    forward_to_deserialize_any! {
+        bytes byte_buf option unit unit_struct seq tuple tuple_struct map struct
+        identifier ignored_any i128 u128
+    }
+}
+
+/// A single string value, parsed into the type asked for.
+struct Value<'a>(&'a str);
+
+impl Value<'_> {
+    fn parse<T: std::str::FromStr>(&self) -> Result<T, ValueError> {
+        self.0
+            .parse()
+            .map_err(|_| de::Error::custom(format!("invalid value {:?}", self.0)))
+    }
+}
+
+macro_rules! parse_value {
+    ($($method:ident => $visit:ident,)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+                visitor.$visit(self.parse()?)
+            }
+        )*
+    };
+}
+
+impl<'de> Deserializer<'de> for Value<'_> {
+    type Error = ValueError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_str(self.0)
+    }
+
+    parse_value! {
+        deserialize_bool => visit_bool,
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_f32 => visit_f32,
+        deserialize_f64 => visit_f64,
+        deserialize_char => visit_char,
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        StrDeserializer::<ValueError>::new(self.0).deserialize_enum(name, variants, visitor)
+    }
+
+    forward_to_deserialize_any! {
+        str string bytes byte_buf unit unit_struct seq tuple tuple_struct map struct
+        identifier ignored_any i128 u128
+    }
+}
+
+impl<'de> IntoDeserializer<'de, ValueError> for Value<'_> {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::request::RequestLimits;
+
+    fn request(target: &str, params: &[(&str, &str)]) -> Request {
+        let head = format!("GET {} HTTP/1.1\r\n\r\n", target);
+        let mut request =
+            Request::parse(&mut Cursor::new(head.as_bytes()), &RequestLimits::default()).unwrap();
+        request.route_params = params
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        request
+    }
+
+    fn path<T: DeserializeOwned>(params: &[(&str, &str)]) -> Result<T, HttpError> {
+        Path::from_request_parts(&mut request("/", params)).map(|Path(value)| value)
+    }
+
+    fn query<T: DeserializeOwned>(target: &str) -> Result<T, HttpError> {
+        Query::from_request_parts(&mut request(target, &[])).map(|Query(value)| value)
+    }
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    #[serde(rename_all = "lowercase")]
+    enum Sort {
+        Asc,
+        Desc,
+    }
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Post {
+        user: u32,
+        slug: String,
+    }
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Search {
+        q: String,
+        page: Option<u32>,
+        sort: Option<Sort>,
+    }
+
+    #[test]
+    fn deserializes_a_single_param() {
+        assert_eq!(path::<u64>(&[("id", "42")]), Ok(42));
+        assert_eq!(path::<String>(&[("slug", "a b")]), Ok("a b".to_string()));
+        assert_eq!(path::<Sort>(&[("sort", "desc")]), Ok(Sort::Desc));
+    }
+
+    #[test]
+    fn deserializes_params_into_structs() {
+        assert_eq!(
+            path::<Post>(&[("user", "7"), ("slug", "hello")]),
+            Ok(Post {
+                user: 7,
+                slug: "hello".into()
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_params_that_dont_fit() {
+        assert_eq!(
+            path::<u64>(&[("id", "abc")]),
+            Err(HttpError::new(
+                HTTPStatus::BAD_REQUEST,
+                "Invalid route params: invalid value \"abc\""
+            ))
+        );
+        assert_eq!(
+            path::<u64>(&[("id", "1"), ("page", "2")]),
+            Err(HttpError::new(
+                HTTPStatus::BAD_REQUEST,
+                "Invalid route params: expected a single value, found 2"
+            ))
+        );
+        assert_eq!(
+            path::<Post>(&[("user", "7")]),
+            Err(HttpError::new(
+                HTTPStatus::BAD_REQUEST,
+                "Invalid route params: missing field `slug`"
+            ))
+        );
+    }
+
+    #[test]
+    fn optional_query_fields_may_be_left_out() {
+        assert_eq!(
+            query::<Search>("/search?q=rust&page=2"),
+            Ok(Search {
+                q: "rust".into(),
+                page: Some(2),
+                sort: None
+            })
+        );
+        assert_eq!(
+            query::<Search>("/search?q=rust&sort=asc"),
+            Ok(Search {
+                q: "rust".into(),
+                page: None,
+                sort: Some(Sort::Asc)
+            })
+        );
+    }
+
+    #[test]
+    fn decodes_the_query_string() {
+        let values = query::<HashMap<String, String>>("/?q=rust+web%20apps&caf%C3%A9=%2B1");
+        assert_eq!(values.as_ref().unwrap()["q"], "rust web apps");
+        assert_eq!(values.as_ref().unwrap()["café"], "+1");
+    }
+
+    #[test]
+    fn rejects_query_strings_that_dont_fit() {
+        assert_eq!(
+            query::<Search>("/search?q=rust&page=two"),
+            Err(HttpError::new(
+                HTTPStatus::BAD_REQUEST,
+                "Invalid query string: invalid value \"two\""
+            ))
+        );
+        assert_eq!(
+            query::<Search>("/search?q=rust&sort=up"),
+            Err(HttpError::new(
+                HTTPStatus::BAD_REQUEST,
+                "Invalid query string: unknown variant `up`, expected `asc` or `desc`"
+            ))
+        );
+        assert_eq!(
+            query::<Search>("/search?page=1"),
+            Err(HttpError::new(
+                HTTPStatus::BAD_REQUEST,
+                "Invalid query string: missing field `q`"
+            ))
+        );
+    }
+}
@@ -0,0 +1,118 @@
+use super::{
+    error::respond_with,
+    extract::{FromRequest, FromRequestParts},
+    request::Request,
+    response::{IntoResponse, Response},
+};
+
+mod private {
+    /// Marks handlers whose arguments all implement `FromRequestParts`.
+    pub struct ViaParts;
+    /// Marks handlers whose last argument implements `FromRequest`.
+    pub struct ViaRequest;
+}
+
+use private::{ViaParts, ViaRequest};
+
+/// A function handling requests to a route. `M` tells apart the supported
+/// signatures, so it never has to be written out:
+///
+/// - `Fn(T1, T2, ..., &mut Response) -> R`, which can work on the response directly,
+/// - `Fn(T1, T2, ...) -> R`, which only returns the response,
+///
+/// with up to 8 arguments implementing [`FromRequestParts`], such as the
+/// extractors of [`crate::extract`], and `R` implementing [`IntoResponse`].
+/// The last argument may instead implement [`FromRequest`] and consume the
+/// request, like `Request` itself.
+pub trait Handler<M>: Send + Sync + 'static {
+    fn call(&self, request: Request, response: &mut Response);
+}
+
+macro_rules! extract {
+    ($extracted:expr, $response:ident) => {
+        match $extracted {
+            Ok(value) => value,
+            Err(e) => return respond_with(&e, $response),
+        }
+    };
+}
+
+macro_rules! impl_handler {
+    ([$($part:ident),*], $last:ident) => {
+        impl<F, R, $($part,)* $last> Handler<(ViaRequest, fn($($part,)* $last) -> R)> for F
+        where
+            F: Fn($($part,)* $last) -> R + Send + Sync + 'static,
+            R: IntoResponse,
+            $($part: FromRequestParts,)*
+            $last: FromRequest,
+        {
+            #[allow(non_snake_case, unused_mut)]
+            fn call(&self, mut request: Request, response: &mut Response) {
+                $(let $part = extract!($part::from_request_parts(&mut request), response);)*
+                let $last = extract!($last::from_request(request), response);
+                self($($part,)* $last).into_response(response);
+            }
+        }
+
+        impl<F, R, $($part,)* $last> Handler<(ViaRequest, fn($($part,)* $last, &mut Response) -> R)>
+            for F
+        where
+            F: Fn($($part,)* $last, &mut Response) -> R + Send + Sync + 'static,
+            R: IntoResponse,
+            $($part: FromRequestParts,)*
+            $last: FromRequest,
+        {
+            #[allow(non_snake_case, unused_mut)]
+            fn call(&self, mut request: Request, response: &mut Response) {
+                $(let $part = extract!($part::from_request_parts(&mut request), response);)*
+                let $last = extract!($last::from_request(request), response);
+                self($($part,)* $last, response).into_response(response);
+            }
+        }
+    };
+    ($($part:ident),*) => {
+        impl<F, R, $($part,)*> Handler<(ViaParts, fn($($part,)*) -> R)> for F
+        where
+            F: Fn($($part,)*) -> R + Send + Sync + 'static,
+            R: IntoResponse,
+            $($part: FromRequestParts,)*
+        {
+            #[allow(non_snake_case, unused_mut, unused_variables)]
+            fn call(&self, mut request: Request, response: &mut Response) {
+                $(let $part = extract!($part::from_request_parts(&mut request), response);)*
+                self($($part,)*).into_response(response);
+            }
+        }
+
+        impl<F, R, $($part,)*> Handler<(ViaParts, fn($($part,)* &mut Response) -> R)> for F
+        where
+            F: Fn($($part,)* &mut Response) -> R + Send + Sync + 'static,
+            R: IntoResponse,
+            $($part: FromRequestParts,)*
+        {
+            #[allow(non_snake_case, unused_mut, unused_variables)]
+            fn call(&self, mut request: Request, response: &mut Response) {
+                $(let $part = extract!($part::from_request_parts(&mut request), response);)*
+                self($($part,)* response).into_response(response);
+            }
+        }
+    };
+}
+
+impl_handler!();
+impl_handler!(T1);
+impl_handler!(T1, T2);
+impl_handler!(T1, T2, T3);
+impl_handler!(T1, T2, T3, T4);
+impl_handler!(T1, T2, T3, T4, T5);
+impl_handler!(T1, T2, T3, T4, T5, T6);
+impl_handler!(T1, T2, T3, T4, T5, T6, T7);
+impl_handler!(T1, T2, T3, T4, T5, T6, T7, T8);
+impl_handler!([], T1);
+impl_handler!([T1], T2);
+impl_handler!([T1, T2], T3);
+impl_handler!([T1, T2, T3], T4);
+impl_handler!([T1, T2, T3, T4], T5);
+impl_handler!([T1, T2, T3, T4, T5], T6);
+impl_handler!([T1, T2, T3, T4, T5, T6], T7);
+impl_handler!([T1, T2, T3, T4, T5, T6, T7], T8);
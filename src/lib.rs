@@ -1,6 +1,6 @@
 use std::fmt::{Debug, Display};
 
-#[derive(Debug, Eq, PartialEq, Hash)]
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
 
 pub enum HTTPMethod {
     GET,
@@ -8,50 +8,197 @@ pub enum HTTPMethod {
     PUT,
     DELETE,
     PATCH,
+    HEAD,
+    OPTIONS,
+    TRACE,
+    CONNECT,
 }
-#[derive(Debug, Eq, PartialEq, Hash)]
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
 
 pub enum HTTPStatus {
+    CONTINUE,
+    SWITCHING_PROTOCOLS,
     SUCCESS,
-    NOT_FOUND,
-    SERVER_ERROR,
+    CREATED,
+    ACCEPTED,
+    NON_AUTHORITATIVE_INFORMATION,
+    NO_CONTENT,
+    RESET_CONTENT,
+    PARTIAL_CONTENT,
+    MULTIPLE_CHOICES,
+    MOVED_PERMANENTLY,
+    FOUND,
+    SEE_OTHER,
+    NOT_MODIFIED,
+    TEMPORARY_REDIRECT,
+    PERMANENT_REDIRECT,
+    /// Same as `FOUND`.
+    REDIRECT,
     BAD_REQUEST,
     UNAUTHORIZED,
+    PAYMENT_REQUIRED,
     FORBIDDEN,
+    NOT_FOUND,
+    METHOD_NOT_ALLOWED,
+    NOT_ACCEPTABLE,
+    PROXY_AUTHENTICATION_REQUIRED,
+    REQUEST_TIMEOUT,
+    CONFLICT,
+    GONE,
+    LENGTH_REQUIRED,
+    PRECONDITION_FAILED,
+    PAYLOAD_TOO_LARGE,
+    URI_TOO_LONG,
+    UNSUPPORTED_MEDIA_TYPE,
+    RANGE_NOT_SATISFIABLE,
+    EXPECTATION_FAILED,
+    IM_A_TEAPOT,
+    MISDIRECTED_REQUEST,
+    UNPROCESSABLE_ENTITY,
+    LOCKED,
+    FAILED_DEPENDENCY,
+    TOO_EARLY,
+    UPGRADE_REQUIRED,
+    PRECONDITION_REQUIRED,
+    TOO_MANY_REQUESTS,
+    REQUEST_HEADER_FIELDS_TOO_LARGE,
+    UNAVAILABLE_FOR_LEGAL_REASONS,
     INTERNAL_SERVER_ERROR,
+    /// Same as `INTERNAL_SERVER_ERROR`.
+    SERVER_ERROR,
+    NOT_IMPLEMENTED,
     BAD_GATEWAY,
     SERVICE_UNAVAILABLE,
-    REDIRECT,
+    GATEWAY_TIMEOUT,
+    HTTP_VERSION_NOT_SUPPORTED,
+    /// Any other status, given as its code and reason phrase. The code must be
+    /// within `100..=999`, line breaks in the reason phrase are not sent.
+    CUSTOM(u16, String),
 }
 
 impl HTTPStatus {
+    /// Whether a response with this status may have a body, which is not the case
+    /// for informational responses, `204 No Content` and `304 Not Modified`.
+    pub fn allows_body(&self) -> bool {
+        let code = self.status_code();
+        !(100..200).contains(&code) && code != 204 && code != 304
+    }
+
     pub fn status_text(&self) -> &str {
         match self {
+            HTTPStatus::CONTINUE => "Continue",
+            HTTPStatus::SWITCHING_PROTOCOLS => "Switching Protocols",
             HTTPStatus::SUCCESS => "OK",
-            HTTPStatus::NOT_FOUND => "Not Found",
-            HTTPStatus::SERVER_ERROR => "Internal Server Error",
+            HTTPStatus::CREATED => "Created",
+            HTTPStatus::ACCEPTED => "Accepted",
+            HTTPStatus::NON_AUTHORITATIVE_INFORMATION => "Non-Authoritative Information",
+            HTTPStatus::NO_CONTENT => "No Content",
+            HTTPStatus::RESET_CONTENT => "Reset Content",
+            HTTPStatus::PARTIAL_CONTENT => "Partial Content",
+            HTTPStatus::MULTIPLE_CHOICES => "Multiple Choices",
+            HTTPStatus::MOVED_PERMANENTLY => "Moved Permanently",
+            HTTPStatus::FOUND => "Found",
+            HTTPStatus::SEE_OTHER => "See Other",
+            HTTPStatus::NOT_MODIFIED => "Not Modified",
+            HTTPStatus::TEMPORARY_REDIRECT => "Temporary Redirect",
+            HTTPStatus::PERMANENT_REDIRECT => "Permanent Redirect",
+            HTTPStatus::REDIRECT => "Found",
             HTTPStatus::BAD_REQUEST => "Bad Request",
             HTTPStatus::UNAUTHORIZED => "Unauthorized",
+            HTTPStatus::PAYMENT_REQUIRED => "Payment Required",
             HTTPStatus::FORBIDDEN => "Forbidden",
+            HTTPStatus::NOT_FOUND => "Not Found",
+            HTTPStatus::METHOD_NOT_ALLOWED => "Method Not Allowed",
+            HTTPStatus::NOT_ACCEPTABLE => "Not Acceptable",
+            HTTPStatus::PROXY_AUTHENTICATION_REQUIRED => "Proxy Authentication Required",
+            HTTPStatus::REQUEST_TIMEOUT => "Request Timeout",
+            HTTPStatus::CONFLICT => "Conflict",
+            HTTPStatus::GONE => "Gone",
+            HTTPStatus::LENGTH_REQUIRED => "Length Required",
+            HTTPStatus::PRECONDITION_FAILED => "Precondition Failed",
+            HTTPStatus::PAYLOAD_TOO_LARGE => "Payload Too Large",
+            HTTPStatus::URI_TOO_LONG => "URI Too Long",
+            HTTPStatus::UNSUPPORTED_MEDIA_TYPE => "Unsupported Media Type",
+            HTTPStatus::RANGE_NOT_SATISFIABLE => "Range Not Satisfiable",
+            HTTPStatus::EXPECTATION_FAILED => "Expectation Failed",
+            HTTPStatus::IM_A_TEAPOT => "I'm a teapot",
+            HTTPStatus::MISDIRECTED_REQUEST => "Misdirected Request",
+            HTTPStatus::UNPROCESSABLE_ENTITY => "Unprocessable Entity",
+            HTTPStatus::LOCKED => "Locked",
+            HTTPStatus::FAILED_DEPENDENCY => "Failed Dependency",
+            HTTPStatus::TOO_EARLY => "Too Early",
+            HTTPStatus::UPGRADE_REQUIRED => "Upgrade Required",
+            HTTPStatus::PRECONDITION_REQUIRED => "Precondition Required",
+            HTTPStatus::TOO_MANY_REQUESTS => "Too Many Requests",
+            HTTPStatus::REQUEST_HEADER_FIELDS_TOO_LARGE => "Request Header Fields Too Large",
+            HTTPStatus::UNAVAILABLE_FOR_LEGAL_REASONS => "Unavailable For Legal Reasons",
             HTTPStatus::INTERNAL_SERVER_ERROR => "Internal Server Error",
+            HTTPStatus::SERVER_ERROR => "Internal Server Error",
+            HTTPStatus::NOT_IMPLEMENTED => "Not Implemented",
             HTTPStatus::BAD_GATEWAY => "Bad Gateway",
             HTTPStatus::SERVICE_UNAVAILABLE => "Service Unavailable",
-            HTTPStatus::REDIRECT => "Redirect",
+            HTTPStatus::GATEWAY_TIMEOUT => "Gateway Timeout",
+            HTTPStatus::HTTP_VERSION_NOT_SUPPORTED => "HTTP Version Not Supported",
+            HTTPStatus::CUSTOM(_, text) => text,
         }
     }
 
     pub fn status_code(&self) -> u16 {
         match self {
+            HTTPStatus::CONTINUE => 100,
+            HTTPStatus::SWITCHING_PROTOCOLS => 101,
             HTTPStatus::SUCCESS => 200,
-            HTTPStatus::NOT_FOUND => 404,
-            HTTPStatus::SERVER_ERROR => 500,
+            HTTPStatus::CREATED => 201,
+            HTTPStatus::ACCEPTED => 202,
+            HTTPStatus::NON_AUTHORITATIVE_INFORMATION => 203,
+            HTTPStatus::NO_CONTENT => 204,
+            HTTPStatus::RESET_CONTENT => 205,
+            HTTPStatus::PARTIAL_CONTENT => 206,
+            HTTPStatus::MULTIPLE_CHOICES => 300,
+            HTTPStatus::MOVED_PERMANENTLY => 301,
+            HTTPStatus::FOUND => 302,
+            HTTPStatus::SEE_OTHER => 303,
+            HTTPStatus::NOT_MODIFIED => 304,
+            HTTPStatus::TEMPORARY_REDIRECT => 307,
+            HTTPStatus::PERMANENT_REDIRECT => 308,
+            HTTPStatus::REDIRECT => 302,
             HTTPStatus::BAD_REQUEST => 400,
             HTTPStatus::UNAUTHORIZED => 401,
+            HTTPStatus::PAYMENT_REQUIRED => 402,
             HTTPStatus::FORBIDDEN => 403,
+            HTTPStatus::NOT_FOUND => 404,
+            HTTPStatus::METHOD_NOT_ALLOWED => 405,
+            HTTPStatus::NOT_ACCEPTABLE => 406,
+            HTTPStatus::PROXY_AUTHENTICATION_REQUIRED => 407,
+            HTTPStatus::REQUEST_TIMEOUT => 408,
+            HTTPStatus::CONFLICT => 409,
+            HTTPStatus::GONE => 410,
+            HTTPStatus::LENGTH_REQUIRED => 411,
+            HTTPStatus::PRECONDITION_FAILED => 412,
+            HTTPStatus::PAYLOAD_TOO_LARGE => 413,
+            HTTPStatus::URI_TOO_LONG => 414,
+            HTTPStatus::UNSUPPORTED_MEDIA_TYPE => 415,
+            HTTPStatus::RANGE_NOT_SATISFIABLE => 416,
+            HTTPStatus::EXPECTATION_FAILED => 417,
+            HTTPStatus::IM_A_TEAPOT => 418,
+            HTTPStatus::MISDIRECTED_REQUEST => 421,
+            HTTPStatus::UNPROCESSABLE_ENTITY => 422,
+            HTTPStatus::LOCKED => 423,
+            HTTPStatus::FAILED_DEPENDENCY => 424,
+            HTTPStatus::TOO_EARLY => 425,
+            HTTPStatus::UPGRADE_REQUIRED => 426,
+            HTTPStatus::PRECONDITION_REQUIRED => 428,
+            HTTPStatus::TOO_MANY_REQUESTS => 429,
+            HTTPStatus::REQUEST_HEADER_FIELDS_TOO_LARGE => 431,
+            HTTPStatus::UNAVAILABLE_FOR_LEGAL_REASONS => 451,
             HTTPStatus::INTERNAL_SERVER_ERROR => 500,
+            HTTPStatus::SERVER_ERROR => 500,
+            HTTPStatus::NOT_IMPLEMENTED => 501,
             HTTPStatus::BAD_GATEWAY => 502,
             HTTPStatus::SERVICE_UNAVAILABLE => 503,
-            HTTPStatus::REDIRECT => 302,
+            HTTPStatus::GATEWAY_TIMEOUT => 504,
+            HTTPStatus::HTTP_VERSION_NOT_SUPPORTED => 505,
+            HTTPStatus::CUSTOM(code, _) => *code,
         }
     }
 }
@@ -68,7 +215,10 @@ impl Display for HTTPMethod {
 }
 
 pub mod cookie;
+pub mod error;
+pub mod extract;
 pub mod file;
+pub mod handler;
 pub mod logging;
 pub mod middleware;
 mod parsers;
@@ -78,5 +228,11 @@ pub mod response;
 pub mod router;
 pub mod server;
 pub mod session;
+mod signal;
+pub mod sse;
+pub mod state;
 pub mod storage;
+pub mod stream;
+pub mod url;
 pub mod utils;
+pub mod websocket;
@@ -2,9 +2,63 @@ use crate::{cookie::Cookie, session::SessionStore};
 
 use super::{request::Request, response::Response};
 
-pub type Middleware = fn(request: &Request, response: &mut Response);
+/// Returned by a middleware to decide whether the request goes on to the next
+/// middleware and the route handler.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flow {
+    Continue,
+    /// Skips the remaining middlewares and the route handler, the middleware is
+    /// expected to have written the response.
+    Stop,
+}
+
+pub type BeforeMiddleware = Box<dyn Fn(&Request, &mut Response) -> Flow + Send + Sync + 'static>;
+pub type AroundMiddleware = Box<dyn Fn(Request, &mut Response, Next) + Send + Sync + 'static>;
+
+pub enum Middleware {
+    /// Runs before the rest of the chain and decides whether it runs at all.
+    Before(BeforeMiddleware),
+    /// Wraps the rest of the chain, which it runs through [`Next::run`]. The
+    /// response can be inspected and changed once `run` returns.
+    Around(AroundMiddleware),
+}
+
+/// The rest of a middleware chain, ending with the route handler.
+pub struct Next<'a> {
+    middlewares: &'a [&'a Middleware],
+    handler: &'a dyn Fn(Request, &mut Response),
+}
+
+impl<'a> Next<'a> {
+    pub(crate) fn new(
+        middlewares: &'a [&'a Middleware],
+        handler: &'a dyn Fn(Request, &mut Response),
+    ) -> Next<'a> {
+        Next {
+            middlewares,
+            handler,
+        }
+    }
+
+    /// Runs the remaining middlewares and the route handler.
+    pub fn run(self, request: Request, response: &mut Response) {
+        let Some((middleware, rest)) = self.middlewares.split_first() else {
+            return (self.handler)(request, response);
+        };
+        let next = Next::new(rest, self.handler);
+        match middleware {
+            Middleware::Before(middleware) => {
+                if middleware(&request, response) == Flow::Continue {
+                    next.run(request, response);
+                }
+            }
+            Middleware::Around(middleware) => middleware(request, response, next),
+        }
+    }
+}
 
-pub fn session_handler(request: &Request, response: &mut Response) {
+pub fn session_handler(request: &Request, response: &mut Response) -> Flow {
     let sess_id = request.session.id();
     response.with_cookie(Cookie::new("session_id", sess_id));
+    Flow::Continue
 }
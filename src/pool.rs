@@ -1,25 +1,83 @@
 use std::{
-    sync::{mpsc, Arc, Mutex},
+    cell::{Cell, RefCell},
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc, Mutex,
+    },
     thread::{self, JoinHandle},
+    time::{Duration, Instant},
 };
 
+use crate::{logging::log, router::panic_message};
+
 pub struct ThreadPool {
-    workers: Vec<Worker>,
+    shared: Arc<Shared>,
     sender: Option<mpsc::Sender<Job>>,
 }
 
+/// The state shared by the pool and its workers.
+struct Shared {
+    receiver: Mutex<mpsc::Receiver<Job>>,
+    workers: Mutex<Vec<Worker>>,
+    next_id: AtomicUsize,
+    /// How many detached jobs are running, and how many may run at once.
+    detached: AtomicUsize,
+    max_detached: AtomicUsize,
+}
+
+thread_local! {
+    /// The pool the current thread works for, if any.
+    static POOL: RefCell<Option<Arc<Shared>>> = const { RefCell::new(None) };
+    /// Whether the job running on the current worker was detached.
+    static DETACHED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Hands the slot of the current worker over to a new one, for jobs that keep
+/// their connection open, such as WebSockets and event streams. The current
+/// worker stops once the job returns. Returns `false` when the pool already runs
+/// its maximum of detached jobs, leaving the job on its worker. Does nothing
+/// outside of a pool.
+pub(crate) fn detach() -> bool {
+    POOL.with_borrow(|pool| {
+        let Some(shared) = pool else {
+            return true;
+        };
+        if DETACHED.get() {
+            return true;
+        }
+        let max = shared.max_detached.load(Ordering::SeqCst);
+        let reserved = shared
+            .detached
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+                (n < max).then_some(n + 1)
+            })
+            .is_ok();
+        if reserved {
+            DETACHED.set(true);
+            Worker::spawn(shared);
+        }
+        reserved
+    })
+}
+
 type Job = Box<dyn FnOnce() + Send + 'static>;
 impl ThreadPool {
     pub fn new(n: usize) -> ThreadPool {
         assert!(n > 0);
         let (sender, receiver) = mpsc::channel();
-        let receiver = Arc::new(Mutex::new(receiver));
-        let mut workers = Vec::with_capacity(n);
-        for i in 0..n {
-            workers.push(Worker::new(i, Arc::clone(&receiver)));
+        let shared = Arc::new(Shared {
+            receiver: Mutex::new(receiver),
+            workers: Mutex::new(Vec::with_capacity(n)),
+            next_id: AtomicUsize::new(0),
+            detached: AtomicUsize::new(0),
+            max_detached: AtomicUsize::new(usize::MAX),
+        });
+        for _ in 0..n {
+            Worker::spawn(&shared);
         }
         ThreadPool {
-            workers,
+            shared,
             sender: Some(sender),
         }
     }
@@ -30,16 +88,64 @@ impl ThreadPool {
         let job = Box::new(f);
         self.sender.as_ref().unwrap().send(job).unwrap();
     }
+
+    /// Sets how many jobs may run detached from the pool at once, each on a
+    /// thread of its own. Unlimited by default.
+    pub fn with_max_detached(&mut self, max: usize) -> &mut Self {
+        self.shared.max_detached.store(max, Ordering::SeqCst);
+        self
+    }
+
+    /// Stops accepting jobs and waits up to `timeout` for the queued and running
+    /// ones to finish, including detached ones. Workers still busy after the
+    /// deadline are left running. Returns whether every job completed in time.
+    pub fn shutdown(&mut self, timeout: Duration) -> bool {
+        drop(self.sender.take());
+        let deadline = Instant::now() + timeout;
+        while Instant::now() < deadline && !self.is_idle() {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let mut drained = true;
+        for mut worker in self.take_workers() {
+            if let Some(thread) = worker.thread.take() {
+                if thread.is_finished() {
+                    let _ = thread.join();
+                } else {
+                    drained = false;
+                }
+            }
+        }
+        drained
+    }
+
+    fn is_idle(&self) -> bool {
+        let workers = self.shared.workers.lock().unwrap();
+        workers.iter().all(|w| w.is_finished())
+    }
+
+    /// Takes the workers out, so they can be joined without holding the lock
+    /// a detaching worker needs.
+    fn take_workers(&self) -> Vec<Worker> {
+        std::mem::take(&mut *self.shared.workers.lock().unwrap())
+    }
 }
 
 impl Drop for ThreadPool {
     fn drop(&mut self) {
         drop(self.sender.take());
-        for worker in &mut self.workers {
-            println!("Shutting down worker {}", worker.id);
+        // Detaching workers may add new ones while the others are joined
+        loop {
+            let workers = self.take_workers();
+            if workers.is_empty() {
+                break;
+            }
+            for mut worker in workers {
+                println!("Shutting down worker {}", worker.id);
 
-            if let Some(thread) = worker.thread.take() {
-                thread.join().unwrap();
+                if let Some(thread) = worker.thread.take() {
+                    thread.join().unwrap();
+                }
             }
         }
     }
@@ -50,22 +156,77 @@ pub struct Worker {
     thread: Option<JoinHandle<()>>,
 }
 impl Worker {
-    pub fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Job>>>) -> Worker {
+    fn new(id: usize, shared: Arc<Shared>) -> Worker {
         Worker {
             id: id,
-            thread: Some(thread::spawn(move || loop {
-                let work = receiver.lock().unwrap().recv();
-                match work {
-                    Ok(message) => {
-                        println!("Worker {} has started its job", id);
-                        message();
-                    }
-                    Err(_) => {
-                        println!("Channel destroyed!");
-                        break;
+            thread: Some(thread::spawn(move || {
+                POOL.set(Some(Arc::clone(&shared)));
+                loop {
+                    let work = shared.receiver.lock().unwrap().recv();
+                    match work {
+                        Ok(message) => {
+                            DETACHED.set(false);
+                            // Keep the worker alive when a job panics
+                            if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(message)) {
+                                let _ = log(format!(
+                                    "Worker {} job panicked: {}",
+                                    id,
+                                    panic_message(&*payload)
+                                ));
+                            }
+                            // A new worker took over the slot of this one
+                            if DETACHED.get() {
+                                shared.detached.fetch_sub(1, Ordering::SeqCst);
+                                break;
+                            }
+                        }
+                        Err(_) => {
+                            println!("Channel destroyed!");
+                            break;
+                        }
                     }
                 }
             })),
         }
     }
+
+    /// Starts a new worker for the pool, forgetting the ones that stopped.
+    fn spawn(shared: &Arc<Shared>) {
+        let id = shared.next_id.fetch_add(1, Ordering::SeqCst);
+        let worker = Worker::new(id, Arc::clone(shared));
+        let mut workers = shared.workers.lock().unwrap();
+        workers.retain(|w| !w.is_finished());
+        workers.push(worker);
+    }
+
+    fn is_finished(&self) -> bool {
+        self.thread
+            .as_ref()
+            .is_none_or(|thread| thread.is_finished())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detached_jobs_are_capped() {
+        let mut pool = ThreadPool::new(1);
+        pool.with_max_detached(1);
+        let (first, first_detached) = mpsc::channel();
+        let (second, second_detached) = mpsc::channel();
+        let (release, released) = mpsc::channel::<()>();
+        pool.execute(move || {
+            first.send(detach()).unwrap();
+            let _ = released.recv();
+        });
+        // Runs on the worker that took over the slot of the first job
+        pool.execute(move || second.send(detach()).unwrap());
+
+        assert!(first_detached.recv().unwrap());
+        assert!(!second_detached.recv().unwrap());
+        release.send(()).unwrap();
+        assert!(pool.shutdown(Duration::from_secs(1)));
+    }
 }
@@ -1,7 +1,9 @@
 use std::{
     collections::HashMap,
-    io::{BufRead, BufReader, Read},
-    net::TcpStream,
+    fmt::Display,
+    io::{self, BufRead, Read},
+    str::FromStr,
+    sync::Arc,
 };
 
 use dotenvy::var;
@@ -14,8 +16,10 @@ use super::{
     file::UploadedFile,
     parsers::{parse_multipart_form_data, parse_url_encoded},
     session::FileSession,
+    state::StateMap,
+    url::{UrlError, UrlGenerator},
     utils::uuid,
-    HTTPMethod,
+    HTTPMethod, HTTPStatus,
 };
 #[derive(Debug)]
 pub struct RequestBody<T>
@@ -30,66 +34,202 @@ where
 pub struct Request {
     pub method: HTTPMethod,
     pub uri: String,
+    pub version: String,
     pub path: String,
     pub body: Vec<u8>,
     pub query: HashMap<String, String>,
     pub headers: HashMap<String, String>,
+    /// Trailer fields sent after a chunked request body.
+    pub trailers: HashMap<String, String>,
     pub route_params: HashMap<String, String>,
     pub session: SessionBackend,
     pub cookies: HashMap<String, String>,
+    pub state: Arc<StateMap>,
+    pub(crate) urls: Arc<UrlGenerator>,
 }
 
+/// Size limits applied while parsing a request.
+#[derive(Debug, Clone)]
+pub struct RequestLimits {
+    /// Maximum size in bytes of the request line and headers combined.
+    pub max_header_size: usize,
+    /// Maximum size in bytes of the request body.
+    pub max_body_size: usize,
+}
+
+impl Default for RequestLimits {
+    fn default() -> Self {
+        RequestLimits {
+            max_header_size: 16 * 1024,
+            max_body_size: 10 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ParseError {
+    /// The connection failed or timed out while the request was being read.
+    Io(io::Error),
+    /// The connection was closed before the full request was received.
+    Incomplete,
+    MalformedRequestLine,
+    UnsupportedMethod(String),
+    InvalidHeader(String),
+    InvalidEncoding,
+    InvalidContentLength,
+    InvalidTransferEncoding,
+    /// Both `Transfer-Encoding` and `Content-Length` were sent.
+    AmbiguousLength,
+    InvalidChunk,
+    HeadersTooLarge,
+    BodyTooLarge,
+}
+
+impl ParseError {
+    /// The status to answer the client with, or `None` when the connection is
+    /// unusable and should just be closed.
+    pub fn status(&self) -> Option<HTTPStatus> {
+        match self {
+            ParseError::Io(_) | ParseError::Incomplete => None,
+            ParseError::HeadersTooLarge => Some(HTTPStatus::REQUEST_HEADER_FIELDS_TOO_LARGE),
+            ParseError::BodyTooLarge => Some(HTTPStatus::PAYLOAD_TOO_LARGE),
+            ParseError::UnsupportedMethod(_) => Some(HTTPStatus::NOT_IMPLEMENTED),
+            _ => Some(HTTPStatus::BAD_REQUEST),
+        }
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::Io(e) => write!(f, "Failed to read request: {}", e),
+            ParseError::Incomplete => write!(f, "Incomplete request"),
+            ParseError::MalformedRequestLine => write!(f, "Malformed request line"),
+            ParseError::UnsupportedMethod(method) => write!(f, "Unsupported method: {:?}", method),
+            ParseError::InvalidHeader(line) => write!(f, "Invalid header: {:?}", line),
+            ParseError::InvalidEncoding => write!(f, "Request head is not valid UTF-8"),
+            ParseError::InvalidContentLength => write!(f, "Invalid Content-Length"),
+            ParseError::InvalidTransferEncoding => write!(f, "Unsupported Transfer-Encoding"),
+            ParseError::AmbiguousLength => {
+                write!(f, "Both Transfer-Encoding and Content-Length were sent")
+            }
+            ParseError::InvalidChunk => write!(f, "Malformed chunked body"),
+            ParseError::HeadersTooLarge => write!(f, "Request headers too large"),
+            ParseError::BodyTooLarge => write!(f, "Request body too large"),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl From<io::Error> for ParseError {
+    fn from(e: io::Error) -> Self {
+        if e.kind() == io::ErrorKind::UnexpectedEof {
+            ParseError::Incomplete
+        } else {
+            ParseError::Io(e)
+        }
+    }
+}
+
+/// Returned by [`Request::param`] when a route param can't be read as the requested type.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParamError {
+    /// The matched route has no param with this name.
+    Missing(String),
+    Invalid {
+        name: String,
+        value: String,
+        reason: String,
+    },
+}
+
+impl Display for ParamError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParamError::Missing(name) => write!(f, "Missing route param `{}`", name),
+            ParamError::Invalid {
+                name,
+                value,
+                reason,
+            } => write!(
+                f,
+                "Invalid route param `{}` ({:?}): {}",
+                name, value, reason
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ParamError {}
+
 impl Request {
-    pub fn parse(mut buf_reader: BufReader<&TcpStream>) -> Request {
-        let mut method = HTTPMethod::GET;
-        let mut uri = String::new();
-        let mut length = 0 as u32;
-        let mut path = String::new();
+    pub fn parse<R: BufRead>(
+        buf_reader: &mut R,
+        limits: &RequestLimits,
+    ) -> Result<Request, ParseError> {
         let mut query = HashMap::new();
-        let mut headers = HashMap::new();
-
-        let all_headers: Vec<String> = buf_reader
-            .by_ref()
-            .lines()
-            .map(|f| f.unwrap())
-            .take_while(|line| !line.is_empty())
-            .collect();
+
+        let mut header_budget = limits.max_header_size;
+        let all_headers = read_head(buf_reader, &mut header_budget)?;
         let mut lines = all_headers.iter();
-        let first_line = lines.next().unwrap();
-        let mut words = first_line.split_whitespace();
-
-        match words.next().unwrap() {
-            "POST" => method = HTTPMethod::POST,
-            "PUT" => method = HTTPMethod::PUT,
-            "DELETE" => method = HTTPMethod::DELETE,
-            "PATCH" => method = HTTPMethod::PATCH,
-            _ => method = HTTPMethod::GET,
+        let first_line = lines.next().ok_or(ParseError::MalformedRequestLine)?;
+        let words: Vec<&str> = first_line.split_whitespace().collect();
+        let [method, uri, version] = words[..] else {
+            return Err(ParseError::MalformedRequestLine);
         };
-
-        uri = words.next().unwrap().to_string();
+        if !is_token(method) || !version.starts_with("HTTP/") {
+            return Err(ParseError::MalformedRequestLine);
+        }
+        let method = match method {
+            "GET" => HTTPMethod::GET,
+            "POST" => HTTPMethod::POST,
+            "PUT" => HTTPMethod::PUT,
+            "DELETE" => HTTPMethod::DELETE,
+            "PATCH" => HTTPMethod::PATCH,
+            "HEAD" => HTTPMethod::HEAD,
+            "OPTIONS" => HTTPMethod::OPTIONS,
+            "TRACE" => HTTPMethod::TRACE,
+            "CONNECT" => HTTPMethod::CONNECT,
+            method => return Err(ParseError::UnsupportedMethod(method.to_owned())),
+        };
+        let uri = uri.to_string();
+        let version = version.to_string();
 
         // Extract request headers
-        for line in lines {
-            let mut header_value = line.trim().split(":");
-            let h = header_value.next().unwrap_or_default().trim();
-            let v = header_value.next().unwrap_or_default().trim();
-            headers.insert(h.to_string(), v.to_string());
-        }
-
-        // Extract request body
-        length = headers
-            .get("Content-Length")
-            .unwrap_or(&"0".to_string())
-            .trim()
-            .parse::<u32>()
-            .unwrap();
+        let headers = parse_fields(lines)?;
 
-        let mut body = vec![0; length as usize];
-        buf_reader.read_exact(&mut body).unwrap();
+        // Extract request body. Only the chunked transfer coding is supported, and
+        // sending Content-Length along with it leaves the body length ambiguous.
+        let mut body = vec![];
+        let mut trailers = HashMap::new();
+        if let Some(encoding) = header_value(&headers, "Transfer-Encoding") {
+            if !encoding.trim().eq_ignore_ascii_case("chunked") {
+                return Err(ParseError::InvalidTransferEncoding);
+            }
+            if header_value(&headers, "Content-Length").is_some() {
+                return Err(ParseError::AmbiguousLength);
+            }
+            body = read_chunked_body(buf_reader, limits.max_body_size)?;
+            trailers = parse_fields(read_fields(buf_reader, &mut header_budget)?.iter())?;
+        } else if let Some(length) = header_value(&headers, "Content-Length") {
+            // `parse` alone would also take a leading sign
+            if length.is_empty() || !length.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(ParseError::InvalidContentLength);
+            }
+            let length = length
+                .parse::<usize>()
+                .map_err(|_| ParseError::InvalidContentLength)?;
+            if length > limits.max_body_size {
+                return Err(ParseError::BodyTooLarge);
+            }
+            body.resize(length, 0);
+            buf_reader.read_exact(&mut body)?;
+        }
 
         // Extract Query Params from request
         let mut path_params = uri.split("?");
-        path = path_params.nth(0).unwrap_or_default().to_owned();
+        let path = path_params.nth(0).unwrap_or_default().to_owned();
         let query_params = path_params.nth(0);
         if let Some(qp) = query_params {
             let key_vals: Vec<&str> = qp.split("&").collect();
@@ -102,22 +242,93 @@ impl Request {
         }
 
         // extract cookies
-        let cookies = if headers.contains_key("Cookie") {
-            parse_cookies(headers.get("Cookie").unwrap())
-        } else {
-            HashMap::new()
+        let cookies = match header_value(&headers, "Cookie") {
+            Some(cookie) => parse_cookies(cookie),
+            None => HashMap::new(),
         };
 
-        Request {
+        Ok(Request {
             method,
             uri,
+            version,
             body,
             query,
             path,
             headers,
+            trailers,
             cookies,
             route_params: HashMap::new(),
             session: SessionBackend::NoSession(NoSession {}),
+            state: Arc::default(),
+            urls: Arc::default(),
+        })
+    }
+
+    /// Fetches the application state of type `T` registered with `with_state`.
+    pub fn state<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        self.state.get::<T>()
+    }
+
+    /// Parses the route param `name` into `T`, e.g. `req.param::<u64>("id")`.
+    pub fn param<T>(&self, name: &str) -> Result<T, ParamError>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        let value = self
+            .route_params
+            .get(name)
+            .ok_or_else(|| ParamError::Missing(name.to_owned()))?;
+        value.parse().map_err(|e: T::Err| ParamError::Invalid {
+            name: name.to_owned(),
+            value: value.to_owned(),
+            reason: e.to_string(),
+        })
+    }
+
+    /// Generates the URL of the route called `name`, see [`UrlGenerator::url`].
+    pub fn url_for<I, K, V>(&self, name: &str, params: I) -> Result<String, UrlError>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<str>,
+        V: Display,
+    {
+        self.urls.url(name, params)
+    }
+
+    /// Looks up a request header, ignoring the case of its name.
+    pub fn header<T: AsRef<str>>(&self, name: T) -> Option<&String> {
+        header_value(&self.headers, name.as_ref())
+    }
+
+    /// The id of the last Server-Sent Event the client received, sent when it reconnects.
+    pub fn last_event_id(&self) -> Option<&String> {
+        self.header("Last-Event-ID")
+    }
+
+    /// Whether this is a request to upgrade the connection to a WebSocket.
+    pub fn is_websocket_upgrade(&self) -> bool {
+        let has_token = |header: &str, token: &str| {
+            self.header(header).is_some_and(|value| {
+                value
+                    .split(',')
+                    .any(|v| v.trim().eq_ignore_ascii_case(token))
+            })
+        };
+        self.method == HTTPMethod::GET
+            && has_token("Connection", "upgrade")
+            && has_token("Upgrade", "websocket")
+    }
+
+    /// Whether the client asked for the connection to stay open after this request.
+    /// HTTP/1.1 connections are persistent unless `Connection: close` is sent,
+    /// HTTP/1.0 ones only when `Connection: keep-alive` is sent.
+    pub fn keep_alive(&self) -> bool {
+        let connection = self.header("Connection").map(|c| c.to_ascii_lowercase());
+        match connection.as_deref() {
+            Some(c) if c.contains("close") => false,
+            Some(c) if c.contains("keep-alive") => true,
+            _ => self.version == "HTTP/1.1",
         }
     }
 
@@ -177,6 +388,145 @@ impl Request {
         }
     }
 }
+fn header_value<'a>(headers: &'a HashMap<String, String>, name: &str) -> Option<&'a String> {
+    headers
+        .iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case(name))
+        .map(|(_, v)| v)
+}
+
+/// Reads the request line and headers up to the blank line that ends them.
+fn read_head<R: BufRead>(
+    buf_reader: &mut R,
+    budget: &mut usize,
+) -> Result<Vec<String>, ParseError> {
+    loop {
+        let line = read_line(buf_reader, budget)?;
+        // Clients may send stray line breaks ahead of the request line
+        if !line.is_empty() {
+            let mut lines = vec![line];
+            lines.extend(read_fields(buf_reader, budget)?);
+            return Ok(lines);
+        }
+    }
+}
+
+/// Reads header or trailer lines up to the blank line that ends them.
+fn read_fields<R: BufRead>(
+    buf_reader: &mut R,
+    budget: &mut usize,
+) -> Result<Vec<String>, ParseError> {
+    let mut lines = vec![];
+    loop {
+        let line = read_line(buf_reader, budget)?;
+        if line.is_empty() {
+            return Ok(lines);
+        }
+        lines.push(line);
+    }
+}
+
+/// Reads a single line without its line break, failing once more than `budget` bytes are consumed.
+fn read_line<R: BufRead>(buf_reader: &mut R, budget: &mut usize) -> Result<String, ParseError> {
+    let mut line = vec![];
+    let read = buf_reader
+        .by_ref()
+        .take(*budget as u64 + 1)
+        .read_until(b'\n', &mut line)?;
+    if read > *budget {
+        return Err(ParseError::HeadersTooLarge);
+    }
+    *budget -= read;
+    if !line.ends_with(b"\n") {
+        return Err(ParseError::Incomplete);
+    }
+
+    line.pop();
+    if line.ends_with(b"\r") {
+        line.pop();
+    }
+    String::from_utf8(line).map_err(|_| ParseError::InvalidEncoding)
+}
+
+/// Whether `s` is a token as defined by RFC 9110, such as a method or a field name.
+fn is_token(s: &str) -> bool {
+    !s.is_empty()
+        && s.bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+/// Parses header or trailer lines. Repeated fields are combined into a single
+/// comma-separated value, except for `Content-Length`, whose values must agree.
+fn parse_fields<'a>(
+    lines: impl Iterator<Item = &'a String>,
+) -> Result<HashMap<String, String>, ParseError> {
+    let mut fields: HashMap<String, String> = HashMap::new();
+    for line in lines {
+        let (name, value) = line
+            .split_once(':')
+            .filter(|(name, _)| is_token(name))
+            .ok_or_else(|| ParseError::InvalidHeader(line.to_string()))?;
+        let value = value.trim();
+        let existing = fields
+            .iter_mut()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v);
+        match existing {
+            Some(existing) if name.eq_ignore_ascii_case("Content-Length") => {
+                if existing != value {
+                    return Err(ParseError::InvalidContentLength);
+                }
+            }
+            Some(existing) => {
+                let separator = if name.eq_ignore_ascii_case("Cookie") {
+                    "; "
+                } else {
+                    ", "
+                };
+                existing.push_str(separator);
+                existing.push_str(value);
+            }
+            None => {
+                fields.insert(name.to_string(), value.to_string());
+            }
+        }
+    }
+    Ok(fields)
+}
+
+/// Decodes a `Transfer-Encoding: chunked` body, leaving the reader at its trailer fields.
+fn read_chunked_body<R: BufRead>(buf_reader: &mut R, limit: usize) -> Result<Vec<u8>, ParseError> {
+    let mut body = vec![];
+    loop {
+        let mut line_budget = 1024;
+        let size_line = read_line(buf_reader, &mut line_budget).map_err(|e| match e {
+            ParseError::HeadersTooLarge => ParseError::InvalidChunk,
+            e => e,
+        })?;
+        // Chunk extensions after `;` carry no meaning for us
+        let size = size_line.split(';').next().unwrap_or_default().trim();
+        if size.is_empty() || !size.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(ParseError::InvalidChunk);
+        }
+        let size = usize::from_str_radix(size, 16).map_err(|_| ParseError::InvalidChunk)?;
+        if size == 0 {
+            return Ok(body);
+        }
+        if size > limit - body.len() {
+            return Err(ParseError::BodyTooLarge);
+        }
+
+        let start = body.len();
+        body.resize(start + size, 0);
+        buf_reader.read_exact(&mut body[start..])?;
+        let mut line_break = [0; 2];
+        buf_reader.read_exact(&mut line_break)?;
+        if &line_break != b"\r\n" {
+            return Err(ParseError::InvalidChunk);
+        }
+    }
+}
+
 fn parse_cookies<T: AsRef<str>>(cookie: T) -> HashMap<String, String> {
     let mut cookies = HashMap::new();
     for cookie_line in cookie.as_ref().split(";") {
@@ -187,3 +537,206 @@ fn parse_cookies<T: AsRef<str>>(cookie: T) -> HashMap<String, String> {
     }
     cookies
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn chunked(body: &str, limit: usize) -> Result<Vec<u8>, ParseError> {
+        read_chunked_body(&mut Cursor::new(body.as_bytes()), limit)
+    }
+
+    fn parse(request: &str) -> Result<Request, ParseError> {
+        Request::parse(
+            &mut Cursor::new(request.as_bytes()),
+            &RequestLimits::default(),
+        )
+    }
+
+    #[test]
+    fn decodes_chunks_ignoring_extensions() {
+        let body = chunked("3;name=value\r\nabc\r\na\r\n0123456789\r\n0\r\n\r\n", 100);
+        assert_eq!(body.unwrap(), b"abc0123456789");
+    }
+
+    #[test]
+    fn accepts_bodies_up_to_the_limit() {
+        assert_eq!(
+            chunked("3\r\nabc\r\n2\r\nde\r\n0\r\n\r\n", 5).unwrap(),
+            b"abcde"
+        );
+        assert!(matches!(
+            chunked("3\r\nabc\r\n3\r\ndef\r\n0\r\n\r\n", 5),
+            Err(ParseError::BodyTooLarge)
+        ));
+    }
+
+    #[test]
+    fn rejects_malformed_chunk_sizes() {
+        for body in [
+            "\r\nabc\r\n0\r\n\r\n",
+            "zz\r\nabc\r\n0\r\n\r\n",
+            "-3\r\nabc\r\n0\r\n\r\n",
+            "+3\r\nabc\r\n0\r\n\r\n",
+            "1ffffffffffffffff\r\n",
+            "3\r\nabcd\r\n0\r\n\r\n",
+        ] {
+            assert!(
+                matches!(chunked(body, 100), Err(ParseError::InvalidChunk)),
+                "{:?}",
+                body
+            );
+        }
+    }
+
+    #[test]
+    fn truncated_chunks_are_incomplete() {
+        assert!(matches!(
+            chunked("5\r\nabc", 100),
+            Err(ParseError::Incomplete)
+        ));
+    }
+
+    #[test]
+    fn reads_trailers_after_the_chunked_body() {
+        let request = parse(
+            "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+             3\r\nabc\r\n0\r\nX-Checksum: 42\r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(request.body, b"abc");
+        assert_eq!(request.trailers.get("X-Checksum").unwrap(), "42");
+    }
+
+    #[test]
+    fn rejects_ambiguous_framing() {
+        let request = "POST / HTTP/1.1\r\nContent-Length: 4\r\n\
+                       Transfer-Encoding: chunked\r\n\r\n0\r\n\r\n";
+        assert!(matches!(parse(request), Err(ParseError::AmbiguousLength)));
+
+        let request = "POST / HTTP/1.1\r\nTransfer-Encoding: gzip, chunked\r\n\r\n0\r\n\r\n";
+        assert!(matches!(
+            parse(request),
+            Err(ParseError::InvalidTransferEncoding)
+        ));
+    }
+
+    #[test]
+    fn rejects_malformed_request_lines() {
+        for request in [
+            "GARBAGE\r\n\r\n",
+            "GET /\r\n\r\n",
+            "GET / FTP/1.1\r\n\r\n",
+            "GET / HTTP/1.1 extra\r\n\r\n",
+            "G(T / HTTP/1.1\r\n\r\n",
+        ] {
+            let error = parse(request).err().unwrap();
+            assert!(
+                matches!(error, ParseError::MalformedRequestLine),
+                "{:?}",
+                request
+            );
+            assert_eq!(error.status(), Some(HTTPStatus::BAD_REQUEST));
+        }
+
+        let error = parse("BREW / HTTP/1.1\r\n\r\n").err().unwrap();
+        assert!(matches!(error, ParseError::UnsupportedMethod(ref m) if m == "BREW"));
+        assert_eq!(error.status(), Some(HTTPStatus::NOT_IMPLEMENTED));
+    }
+
+    #[test]
+    fn rejects_heads_over_the_limit() {
+        let limits = RequestLimits {
+            max_header_size: 64,
+            max_body_size: 64,
+        };
+        let request = format!("GET / HTTP/1.1\r\nX-Long: {}\r\n\r\n", "a".repeat(64));
+        let error = Request::parse(&mut Cursor::new(request.as_bytes()), &limits)
+            .err()
+            .unwrap();
+        assert!(matches!(error, ParseError::HeadersTooLarge));
+        assert_eq!(
+            error.status(),
+            Some(HTTPStatus::REQUEST_HEADER_FIELDS_TOO_LARGE)
+        );
+    }
+
+    #[test]
+    fn rejects_declared_bodies_over_the_limit() {
+        let limits = RequestLimits {
+            max_header_size: 1024,
+            max_body_size: 4,
+        };
+        let request = "POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello";
+        let error = Request::parse(&mut Cursor::new(request.as_bytes()), &limits)
+            .err()
+            .unwrap();
+        assert!(matches!(error, ParseError::BodyTooLarge));
+        assert_eq!(error.status(), Some(HTTPStatus::PAYLOAD_TOO_LARGE));
+    }
+
+    #[test]
+    fn rejects_invalid_content_lengths() {
+        for length in ["abc", "+5", "-5", "5 5", "0x5", ""] {
+            let request = format!("POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\nhello", length);
+            assert!(
+                matches!(parse(&request), Err(ParseError::InvalidContentLength)),
+                "{:?}",
+                length
+            );
+        }
+
+        let request = "POST / HTTP/1.1\r\nContent-Length: 5\r\ncontent-length: 4\r\n\r\nhello";
+        assert!(matches!(
+            parse(request),
+            Err(ParseError::InvalidContentLength)
+        ));
+
+        let request = "POST / HTTP/1.1\r\nContent-Length: 5\r\nContent-Length: 5\r\n\r\nhello";
+        assert_eq!(parse(request).unwrap().body, b"hello");
+    }
+
+    #[test]
+    fn rejects_header_lines_that_are_not_utf8() {
+        let request = b"GET / HTTP/1.1\r\nX-Name: caf\xe9\r\n\r\n";
+        let error = Request::parse(&mut Cursor::new(&request[..]), &RequestLimits::default())
+            .err()
+            .unwrap();
+        assert!(matches!(error, ParseError::InvalidEncoding));
+        assert_eq!(error.status(), Some(HTTPStatus::BAD_REQUEST));
+    }
+
+    #[test]
+    fn truncated_bodies_are_incomplete() {
+        let error = parse("POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\nhello")
+            .err()
+            .unwrap();
+        assert!(matches!(error, ParseError::Incomplete));
+        assert_eq!(error.status(), None);
+    }
+
+    #[test]
+    fn reports_missing_and_invalid_params() {
+        let mut request = parse("GET /users/abc HTTP/1.1\r\n\r\n").unwrap();
+        request.route_params.insert("id".into(), "abc".into());
+        request.route_params.insert("page".into(), "2".into());
+        assert_eq!(request.param::<u32>("page"), Ok(2));
+        assert_eq!(request.param::<String>("id"), Ok("abc".to_string()));
+
+        assert_eq!(
+            request.param::<u64>("user"),
+            Err(ParamError::Missing("user".into()))
+        );
+        let error = request.param::<u64>("id").unwrap_err();
+        assert!(matches!(
+            &error,
+            ParamError::Invalid { name, value, .. } if name == "id" && value == "abc"
+        ));
+        assert_eq!(
+            error.to_string(),
+            "Invalid route param `id` (\"abc\"): invalid digit found in string"
+        );
+    }
+}
@@ -1,18 +1,162 @@
 use std::{
     collections::HashMap,
     fs::File,
-    io::{Read, Write},
+    io::{self, Read, Write},
     net::TcpStream,
     path::Path,
 };
 
-use super::{cookie::Cookie, HTTPStatus};
+use serde::Serialize;
+
+use super::{
+    cookie::Cookie,
+    error::{respond_with, HttpError, ResponseError},
+    logging::log,
+    pool,
+    sse::EventStream,
+    stream::ChunkedWriter,
+    websocket::{accept_key, WebSocket},
+    HTTPStatus,
+};
+/// The response to a request. Its status, headers and body are buffered and only
+/// written to the client once the handler and all middlewares are done, except
+/// for streamed responses, which are sent as soon as they are started.
 #[derive(Debug)]
 pub struct Response {
     stream: TcpStream,
     headers: HashMap<String, String>,
     cookies: Vec<Cookie>,
     status: HTTPStatus,
+    keep_alive: bool,
+    body: Vec<u8>,
+    content_type: String,
+    sent: bool,
+    omit_body: bool,
+    /// An error to render with the router's error handler once the route handler returns.
+    error: Option<HttpError>,
+}
+
+/// The value returned by a route handler, written to the response once it returns.
+pub trait IntoResponse {
+    fn into_response(self, response: &mut Response);
+}
+
+impl IntoResponse for () {
+    fn into_response(self, _: &mut Response) {}
+}
+
+impl<T: IntoResponse, E: ResponseError> IntoResponse for Result<T, E> {
+    fn into_response(self, response: &mut Response) {
+        match self {
+            Ok(value) => value.into_response(response),
+            Err(e) => respond_with(&e, response),
+        }
+    }
+}
+
+impl IntoResponse for String {
+    fn into_response(self, response: &mut Response) {
+        response.text(self);
+    }
+}
+
+impl IntoResponse for &'static str {
+    fn into_response(self, response: &mut Response) {
+        response.text(self);
+    }
+}
+
+/// Sent as `application/octet-stream`.
+impl IntoResponse for Vec<u8> {
+    fn into_response(self, response: &mut Response) {
+        response.respond(&self, "application/octet-stream");
+    }
+}
+
+/// A body with its content type, such as `("image/png", bytes)`.
+impl IntoResponse for (&'static str, Vec<u8>) {
+    fn into_response(self, response: &mut Response) {
+        response.respond(&self.1, self.0);
+    }
+}
+
+/// An empty response with this status.
+impl IntoResponse for HTTPStatus {
+    fn into_response(self, response: &mut Response) {
+        response.with_status(self);
+    }
+}
+
+impl<T: IntoResponse> IntoResponse for (HTTPStatus, T) {
+    fn into_response(self, response: &mut Response) {
+        response.with_status(self.0);
+        self.1.into_response(response);
+    }
+}
+
+/// A value sent as JSON.
+#[derive(Debug, Clone)]
+pub struct Json<T>(pub T);
+
+impl<T: Serialize> IntoResponse for Json<T> {
+    fn into_response(self, response: &mut Response) {
+        match serde_json::to_vec(&self.0) {
+            Ok(json) => response.respond(&json, "application/json"),
+            Err(e) => {
+                let _ = log(format!("Failed to serialize response: {}", e));
+                response.error(HTTPStatus::INTERNAL_SERVER_ERROR);
+            }
+        }
+    }
+}
+
+/// A body sent as `text/html`.
+#[derive(Debug, Clone)]
+pub struct Html<T>(pub T);
+
+impl<T: Into<String>> IntoResponse for Html<T> {
+    fn into_response(self, response: &mut Response) {
+        response.html(self.0.into());
+    }
+}
+
+/// Redirects the client to another URL.
+#[derive(Debug, Clone)]
+pub struct Redirect {
+    to: String,
+    status: HTTPStatus,
+}
+
+impl Redirect {
+    /// A `302 Found` redirect.
+    pub fn to<T: Into<String>>(to: T) -> Redirect {
+        Redirect {
+            to: to.into(),
+            status: HTTPStatus::REDIRECT,
+        }
+    }
+
+    /// A `307 Temporary Redirect`, which keeps the method and body of the request.
+    pub fn temporary<T: Into<String>>(to: T) -> Redirect {
+        Redirect {
+            to: to.into(),
+            status: HTTPStatus::TEMPORARY_REDIRECT,
+        }
+    }
+
+    /// A `308 Permanent Redirect`, which keeps the method and body of the request.
+    pub fn permanent<T: Into<String>>(to: T) -> Redirect {
+        Redirect {
+            to: to.into(),
+            status: HTTPStatus::PERMANENT_REDIRECT,
+        }
+    }
+}
+
+impl IntoResponse for Redirect {
+    fn into_response(self, response: &mut Response) {
+        response.redirect_with_status(self.to, self.status);
+    }
 }
 
 impl Response {
@@ -22,10 +166,98 @@ impl Response {
             status: HTTPStatus::SUCCESS,
             headers: HashMap::new(),
             cookies: vec![],
+            keep_alive: false,
+            body: vec![],
+            content_type: "text/plain".to_string(),
+            sent: false,
+            omit_body: false,
+            error: None,
         };
     }
 
+    pub fn status(&self) -> &HTTPStatus {
+        &self.status
+    }
+
+    pub fn headers(&self) -> &HashMap<String, String> {
+        &self.headers
+    }
+
+    /// Looks up a response header, ignoring the case of its name.
+    pub fn header<T: AsRef<str>>(&self, name: T) -> Option<&String> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name.as_ref()))
+            .map(|(_, v)| v)
+    }
+
+    pub fn cookies(&self) -> &[Cookie] {
+        &self.cookies
+    }
+
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    pub fn content_type(&self) -> &str {
+        &self.content_type
+    }
+
+    /// Whether the response was already written to the client, which is the case
+    /// for streams, Server-Sent Events and WebSocket upgrades.
+    pub fn is_sent(&self) -> bool {
+        self.sent
+    }
+
+    pub fn with_body<T: Into<Vec<u8>>>(&mut self, body: T) -> &mut Self {
+        self.body = body.into();
+        self
+    }
+
+    pub fn with_content_type<T: Into<String>>(&mut self, content_type: T) -> &mut Self {
+        self.content_type = content_type.into();
+        self
+    }
+
+    /// Whether the connection stays open for further requests after this response.
+    pub(crate) fn with_keep_alive(&mut self, keep_alive: bool) -> &mut Self {
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    /// Answers with `error`, rendered by the error handler of the router once the
+    /// route handler returns, or as plain text when there is none.
+    pub fn error<T: Into<HttpError>>(&mut self, error: T) {
+        let error = error.into();
+        self.with_status(error.status.clone()).text(&error.message);
+        self.error = Some(error);
+    }
+
+    pub(crate) fn take_error(&mut self) -> Option<HttpError> {
+        self.error.take()
+    }
+
+    /// Drops the status, headers, cookies and body set so far.
+    pub(crate) fn clear(&mut self) -> &mut Self {
+        self.status = HTTPStatus::SUCCESS;
+        self.headers.clear();
+        self.cookies.clear();
+        self.body.clear();
+        self.content_type = "text/plain".to_string();
+        self.error = None;
+        self
+    }
+
+    /// Sends the headers of the response without its body, as `HEAD` requests expect.
+    pub(crate) fn with_omit_body(&mut self, omit_body: bool) -> &mut Self {
+        self.omit_body = omit_body;
+        self
+    }
+
+    /// Sets the status of the response. Panics for `CUSTOM` codes outside of `100..=999`.
     pub fn with_status(&mut self, status: HTTPStatus) -> &mut Self {
+        let code = status.status_code();
+        assert!((100..=999).contains(&code), "Invalid status code {}", code);
         self.status = status;
         self
     }
@@ -61,10 +293,69 @@ impl Response {
     }
 
     fn respond<S: AsRef<str>>(&mut self, content: &[u8], content_type: S) {
+        self.body = content.to_vec();
+        self.content_type = content_type.as_ref().to_string();
+    }
+
+    /// Writes the buffered response to the client, unless it was already sent.
+    pub(crate) fn send(&mut self) -> io::Result<()> {
+        if self.sent {
+            return Ok(());
+        }
+        // Statuses without a body are sent without framing headers
+        let framing = if self.status.allows_body() {
+            format!(
+                "Content-Length: {}\r\nContent-Type: {}\r\n",
+                self.body.len(),
+                self.content_type
+            )
+        } else {
+            self.body.clear();
+            String::new()
+        };
+        self.write_head(&framing)?;
+        if !self.omit_body {
+            self.stream.write_all(&self.body)?;
+        }
+        self.stream.flush()
+    }
+
+    /// Completes the WebSocket handshake for a client's `Sec-WebSocket-Key`
+    /// and hands the connection over to the returned socket.
+    pub(crate) fn upgrade(&mut self, key: &str) -> io::Result<WebSocket> {
+        self.status = HTTPStatus::SWITCHING_PROTOCOLS;
+        self.with_header("Upgrade", "websocket")
+            .with_header("Sec-WebSocket-Accept", accept_key(key));
+        let head = self.head("Connection: Upgrade\r\n");
+        self.sent = true;
+        self.stream.write_all(head.as_bytes())?;
+        WebSocket::new(self.stream.try_clone()?)
+    }
+
+    /// Writes the status line and headers, `framing` being the header lines that
+    /// delimit and describe the body (`Content-Length` or `Transfer-Encoding`,
+    /// and `Content-Type`).
+    fn write_head(&mut self, framing: &str) -> io::Result<()> {
+        self.sent = true;
+        let head = self.head(&format!(
+            "Connection: {}\r\n{}",
+            if self.keep_alive {
+                "keep-alive"
+            } else {
+                "close"
+            },
+            framing
+        ));
+        self.stream.write_all(head.as_bytes())
+    }
+
+    /// Builds the status line, headers and cookies followed by `extra` header lines.
+    fn head(&self, extra: &str) -> String {
+        // A line break in a custom reason phrase would start a new header
         let status_line = format!(
             "HTTP/1.1 {} {}\r\n",
             self.status.status_code(),
-            self.status.status_text()
+            self.status.status_text().replace(['\r', '\n'], "")
         );
         let mut headers = String::new();
         for (key, value) in &self.headers {
@@ -74,16 +365,43 @@ impl Response {
         for cookie in &self.cookies {
             cookies.push_str(&format!("Set-Cookie: {}\r\n", cookie.to_string()));
         }
-        let response = format!(
-            "{}{}{}Connection: close\r\nContent-Length: {}\r\nContent-Type: {}\r\n\r\n",
-            status_line,
-            headers,
-            cookies,
-            content.len(),
+        format!("{}{}{}{}\r\n", status_line, headers, cookies, extra)
+    }
+
+    /// Starts a `Transfer-Encoding: chunked` response and returns a writer for
+    /// its body, so large or slowly produced content never has to be held in memory.
+    /// Every write is sent to the client as one chunk. The status and headers are
+    /// sent right away, so middlewares can no longer change them afterwards.
+    pub fn stream<S: AsRef<str>>(&mut self, content_type: S) -> io::Result<ChunkedWriter<'_>> {
+        self.write_head(&format!(
+            "Transfer-Encoding: chunked\r\nContent-Type: {}\r\n",
             content_type.as_ref()
-        );
-        self.stream.write_all(response.as_bytes()).unwrap();
-        self.stream.write_all(content).unwrap();
+        ))?;
+        Ok(ChunkedWriter::new(&mut self.stream, self.omit_body))
+    }
+
+    /// Starts a Server-Sent Events response. Events are pushed through the returned
+    /// stream for as long as the handler keeps it open. The handler keeps its
+    /// thread, while a new worker takes its place in the pool, unless the pool
+    /// already runs its maximum of long-lived connections.
+    pub fn sse(&mut self) -> io::Result<EventStream<'_>> {
+        pool::detach();
+        self.with_header("Cache-Control", "no-cache");
+        Ok(EventStream::new(self.stream("text/event-stream")?))
+    }
+
+    /// Streams every item of `chunks` to the client as a chunked response.
+    pub fn stream_chunks<S, I>(&mut self, content_type: S, chunks: I) -> io::Result<()>
+    where
+        S: AsRef<str>,
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        let mut writer = self.stream(content_type)?;
+        for chunk in chunks {
+            writer.write_all(chunk.as_ref())?;
+        }
+        writer.finish()
     }
 
     pub fn json<T: AsRef<str>>(&mut self, json: T) {
@@ -94,6 +412,10 @@ impl Response {
         self.respond(text.as_ref().as_bytes(), "text/plain");
     }
 
+    pub fn html<T: AsRef<str>>(&mut self, html: T) {
+        self.respond(html.as_ref().as_bytes(), "text/html");
+    }
+
     pub fn file(&mut self, path: &Path) {
         if path.is_file() && path.exists() {
             if let Ok(mut file) = File::open(path) {
@@ -120,7 +442,13 @@ impl Response {
     }
 
     pub fn redirect<T: AsRef<str>>(&mut self, to: T) {
-        self.with_status(HTTPStatus::REDIRECT)
+        self.redirect_with_status(to, HTTPStatus::REDIRECT);
+    }
+
+    /// Redirects with another status than `302 Found`, such as `MOVED_PERMANENTLY`
+    /// or `PERMANENT_REDIRECT`.
+    pub fn redirect_with_status<T: AsRef<str>>(&mut self, to: T, status: HTTPStatus) {
+        self.with_status(status)
             .with_header("Location", to.as_ref())
             .text("");
     }
@@ -1,15 +1,44 @@
 use std::{
-    collections::{HashMap, HashSet},
+    any::Any,
+    collections::HashMap,
+    fmt::{Debug, Display},
+    panic::{self, AssertUnwindSafe},
     path::Path,
+    sync::{Arc, OnceLock},
     vec,
 };
 
 use dotenvy::var;
 use regex::Regex;
+use urlencoding::decode;
 
-use crate::{middleware::session_handler, utils::cleanup_path};
+use crate::{
+    error::HttpError,
+    handler::Handler,
+    logging::log,
+    middleware::session_handler,
+    pool,
+    state::StateMap,
+    url::{UrlError, UrlGenerator},
+    utils::cleanup_path,
+};
+
+use super::{
+    middleware::{Flow, Middleware, Next},
+    request::Request,
+    response::Response,
+    websocket::WebSocket,
+    HTTPMethod, HTTPStatus,
+};
 
-use super::{middleware::Middleware, request::Request, response::Response, HTTPMethod};
+/// The message a panic was raised with, when it is a string.
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(|s| s.as_str()))
+        .unwrap_or("unknown error")
+}
 
 fn strip_braces(s: &str) -> &str {
     s.strip_prefix('{')
@@ -17,21 +46,150 @@ fn strip_braces(s: &str) -> &str {
         .unwrap_or(s)
 }
 
+/// A segment of a route pattern, parsed when the route is registered.
+#[derive(Debug, Clone)]
+pub(crate) enum Segment {
+    Static(String),
+    /// `{name}` or `{name:constraint}`, optional when written `{name?}` or `{name?:constraint}`.
+    Param {
+        name: String,
+        constraint: Option<Constraint>,
+        optional: bool,
+    },
+    /// `*`, matching the rest of the path.
+    Wildcard,
+    /// `{*name}`, capturing the rest of the path, possibly empty.
+    CatchAll(String),
+}
+
+/// Restricts the values a param segment matches.
+#[derive(Debug, Clone)]
+pub(crate) enum Constraint {
+    /// `{id:int}`, an optionally signed integer.
+    Int,
+    /// `{id:uuid}`, a hyphenated UUID.
+    Uuid,
+    /// Any other constraint, used as a regex the whole segment must match.
+    Regex(Regex),
+}
+
+impl Segment {
+    pub(crate) fn parse(segment: &str) -> Segment {
+        if segment == "*" {
+            return Segment::Wildcard;
+        }
+        if !(segment.starts_with('{') && segment.ends_with('}')) {
+            return Segment::Static(segment.to_owned());
+        }
+        let param = strip_braces(segment);
+        if let Some(name) = param.strip_prefix('*') {
+            return Segment::CatchAll(name.to_owned());
+        }
+        let (name, constraint) = match param.split_once(':') {
+            Some((name, constraint)) => (name, Some(Constraint::parse(constraint))),
+            None => (param, None),
+        };
+        let (name, optional) = match name.strip_suffix('?') {
+            Some(name) => (name, true),
+            None => (name, false),
+        };
+        Segment::Param {
+            name: name.to_owned(),
+            constraint,
+            optional,
+        }
+    }
+}
+
+impl Constraint {
+    fn parse(constraint: &str) -> Constraint {
+        match constraint {
+            "int" => Constraint::Int,
+            "uuid" => Constraint::Uuid,
+            pattern => Constraint::Regex(
+                Regex::new(&format!("^(?:{})$", pattern))
+                    .unwrap_or_else(|e| panic!("Invalid route param pattern {:?}: {}", pattern, e)),
+            ),
+        }
+    }
+
+    pub(crate) fn matches(&self, value: &str) -> bool {
+        match self {
+            Constraint::Int => {
+                let digits = value.strip_prefix('-').unwrap_or(value);
+                !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit())
+            }
+            Constraint::Uuid => {
+                value.len() == 36
+                    && value.char_indices().all(|(i, c)| match i {
+                        8 | 13 | 18 | 23 => c == '-',
+                        _ => c.is_ascii_hexdigit(),
+                    })
+            }
+            Constraint::Regex(regex) => regex.is_match(value),
+        }
+    }
+}
+
 pub struct Router {
     pub routes: Box<Route>,
     registered_middlewares: HashMap<String, Middleware>,
-    middlewares: HashSet<String>,
+    global_middlewares: Vec<String>,
+    middlewares: Vec<String>,
+    excluded_middlewares: Vec<String>,
     prefix: String,
+    state: Arc<StateMap>,
+    urls: OnceLock<Arc<UrlGenerator>>,
 }
-#[derive(Debug)]
 pub struct Route {
     path: String,
+    segment: Segment,
+    /// The full path pattern of the route, including group prefixes.
+    pattern: String,
+    name: Option<String>,
     handlers: HashMap<HTTPMethod, RouteHandler>,
+    websocket: Option<WebSocketHandler>,
+    /// Handles unmatched requests below this route, set with `Router::fallback`.
+    fallback: Option<RouteHandler>,
+    /// Renders errors below this route, set with `Router::on_error`.
+    error_handler: Option<ErrorHandler>,
     children: Vec<Box<Route>>,
-    middlewares: HashSet<String>,
+    middlewares: Vec<String>,
+    excluded_middlewares: Vec<String>,
+}
+
+/// Appends the middleware names not in `list` yet, keeping their order.
+fn push_middlewares(
+    list: &mut Vec<String>,
+    middlewares: impl IntoIterator<Item = impl AsRef<str>>,
+) {
+    for middleware in middlewares {
+        let middleware = middleware.as_ref();
+        if !list.iter().any(|m| m == middleware) {
+            list.push(middleware.to_string());
+        }
+    }
 }
 
 fn merge_trees(target: &mut Route, source: Route) {
+    target.handlers.extend(source.handlers);
+    if source.websocket.is_some() {
+        target.websocket = source.websocket;
+    }
+    if source.name.is_some() {
+        target.name = source.name;
+    }
+    if source.fallback.is_some() {
+        target.fallback = source.fallback;
+    }
+    if source.error_handler.is_some() {
+        target.error_handler = source.error_handler;
+    }
+    push_middlewares(&mut target.middlewares, source.middlewares);
+    push_middlewares(
+        &mut target.excluded_middlewares,
+        source.excluded_middlewares,
+    );
     for source_child in source.children {
         if let Some(target_child) = target
             .children
@@ -46,42 +204,286 @@ fn merge_trees(target: &mut Route, source: Route) {
 }
 
 impl Route {
+    fn new(path: &str, pattern: String) -> Route {
+        Route {
+            path: path.to_string(),
+            segment: Segment::parse(path),
+            pattern,
+            name: None,
+            handlers: HashMap::new(),
+            websocket: None,
+            fallback: None,
+            error_handler: None,
+            children: vec![],
+            middlewares: vec![],
+            excluded_middlewares: vec![],
+        }
+    }
+
     pub fn extend(&mut self, tree: Box<Route>) {
         merge_trees(self, *tree);
     }
 
-    pub fn with_middlewares(&mut self, middlewares: impl IntoIterator<Item = impl AsRef<str>>) {
-        let middlewares: HashSet<String> = middlewares
-            .into_iter()
-            .map(|s| s.as_ref().to_string())
-            .into_iter()
-            .collect();
-        self.middlewares.extend(middlewares);
+    /// Adds middlewares to this route. They run in the given order, after the
+    /// ones inherited from the router and its groups.
+    pub fn with_middlewares(
+        &mut self,
+        middlewares: impl IntoIterator<Item = impl AsRef<str>>,
+    ) -> &mut Self {
+        push_middlewares(&mut self.middlewares, middlewares);
+        self
+    }
+
+    /// Skips middlewares this route would otherwise inherit from the router,
+    /// its groups or the global middlewares.
+    pub fn without_middlewares(
+        &mut self,
+        middlewares: impl IntoIterator<Item = impl AsRef<str>>,
+    ) -> &mut Self {
+        push_middlewares(&mut self.excluded_middlewares, middlewares);
+        self
+    }
+
+    /// Whether this route has a handler for the request. `HEAD` is answered by
+    /// the `GET` handler and `OPTIONS` by any route, unless they have their own.
+    fn accepts(&self, request: &Request) -> bool {
+        match request.method {
+            _ if self.handlers.contains_key(&request.method) => true,
+            HTTPMethod::HEAD => self.handlers.contains_key(&HTTPMethod::GET),
+            HTTPMethod::OPTIONS => self.has_handlers(),
+            _ => self.websocket.is_some() && request.is_websocket_upgrade(),
+        }
+    }
+
+    /// Names the route, so URLs to it can be generated with `Router::url` or `Request::url_for`.
+    pub fn name<T: Into<String>>(&mut self, name: T) -> &mut Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Adds the named routes of this tree to `urls`.
+    fn collect_names(&self, urls: &mut UrlGenerator) {
+        if let Some(name) = &self.name {
+            urls.insert(name.clone(), &self.pattern);
+        }
+        for child in &self.children {
+            child.collect_names(urls);
+        }
+    }
+
+    fn has_handlers(&self) -> bool {
+        !self.handlers.is_empty() || self.websocket.is_some()
+    }
+
+    /// The methods this route answers, as listed in the `Allow` header.
+    fn allowed_methods(&self) -> String {
+        let has = |method: &HTTPMethod| {
+            self.handlers.contains_key(method)
+                || match method {
+                    HTTPMethod::GET => self.websocket.is_some(),
+                    HTTPMethod::HEAD => self.handlers.contains_key(&HTTPMethod::GET),
+                    HTTPMethod::OPTIONS => true,
+                    _ => false,
+                }
+        };
+        [
+            HTTPMethod::GET,
+            HTTPMethod::HEAD,
+            HTTPMethod::POST,
+            HTTPMethod::PUT,
+            HTTPMethod::PATCH,
+            HTTPMethod::DELETE,
+            HTTPMethod::OPTIONS,
+            HTTPMethod::TRACE,
+            HTTPMethod::CONNECT,
+        ]
+        .iter()
+        .filter(|method| has(method))
+        .map(|method| method.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+    }
+
+    /// Finds the route below this one matching `segments` for which `accepts` holds.
+    ///
+    /// Static children are tried before params, params before wildcards, and
+    /// skipping optional params comes last. When a branch dead-ends, the next
+    /// candidate is tried, so registration order never makes a route unreachable.
+    fn find<'a>(
+        &'a self,
+        segments: &[&str],
+        accepts: &dyn Fn(&Route) -> bool,
+        params: &mut HashMap<String, String>,
+    ) -> Option<&'a Route> {
+        if segments.is_empty() && accepts(self) {
+            return Some(self);
+        }
+        if let Some(route) = self.find_child(segments, accepts, params) {
+            return Some(route);
+        }
+
+        // Children that can match without taking a segment
+        for child in &self.children {
+            match &child.segment {
+                Segment::Param { optional: true, .. } => {
+                    if let Some(route) = child.find(segments, accepts, params) {
+                        return Some(route);
+                    }
+                }
+                // A wildcard also matches the path it was registered under
+                Segment::Wildcard if segments.is_empty() && accepts(child) => {
+                    return Some(child);
+                }
+                Segment::CatchAll(name) if segments.is_empty() && accepts(child) => {
+                    params.insert(name.to_owned(), String::new());
+                    return Some(child);
+                }
+                _ => {}
+            }
+        }
+        None
+    }
+
+    /// Finds the value of `get` on the deepest route along `segments` that has one,
+    /// following static segments before params.
+    fn find_nearest<'a, T: ?Sized>(
+        &'a self,
+        segments: &[&str],
+        get: &dyn Fn(&'a Route) -> Option<&'a T>,
+    ) -> Option<&'a T> {
+        let here = get(self);
+        let Some((segment, rest)) = segments.split_first() else {
+            return here;
+        };
+        let child = self
+            .children
+            .iter()
+            .find(|child| matches!(&child.segment, Segment::Static(path) if path == segment))
+            .or_else(|| {
+                self.children.iter().find(|child| match &child.segment {
+                    Segment::Param { constraint, .. } => {
+                        constraint.as_ref().is_none_or(|c| c.matches(segment))
+                    }
+                    _ => false,
+                })
+            });
+        child
+            .and_then(|child| child.find_nearest(rest, get))
+            .or(here)
+    }
+
+    /// Matches the first of `segments` against the children of this route.
+    fn find_child<'a>(
+        &'a self,
+        segments: &[&str],
+        accepts: &dyn Fn(&Route) -> bool,
+        params: &mut HashMap<String, String>,
+    ) -> Option<&'a Route> {
+        let (segment, rest) = segments.split_first()?;
+
+        let statics = self
+            .children
+            .iter()
+            .filter(|child| matches!(&child.segment, Segment::Static(path) if path == segment));
+        for child in statics {
+            if let Some(route) = child.find(rest, accepts, params) {
+                return Some(route);
+            }
+        }
+
+        // Constrained params are more specific, so they are tried first
+        let params_of = |constrained: bool| {
+            self.children
+                .iter()
+                .filter_map(move |child| match &child.segment {
+                    Segment::Param {
+                        name, constraint, ..
+                    } if constraint.is_some() == constrained => Some((child, name, constraint)),
+                    _ => None,
+                })
+        };
+        for (child, name, constraint) in params_of(true).chain(params_of(false)) {
+            if constraint.as_ref().is_some_and(|c| !c.matches(segment)) {
+                continue;
+            }
+            let previous = params.insert(name.to_owned(), segment.to_string());
+            if let Some(route) = child.find(rest, accepts, params) {
+                return Some(route);
+            }
+            match previous {
+                Some(value) => params.insert(name.to_owned(), value),
+                None => params.remove(name),
+            };
+        }
+
+        for child in &self.children {
+            match &child.segment {
+                // Wildcards take the rest of the path unless a deeper route matches
+                Segment::Wildcard => {
+                    let route = child
+                        .find(rest, accepts, params)
+                        .or_else(|| accepts(child).then_some(child.as_ref()));
+                    if route.is_some() {
+                        return route;
+                    }
+                }
+                Segment::CatchAll(name) if accepts(child) => {
+                    params.insert(name.to_owned(), segments.join("/"));
+                    return Some(child);
+                }
+                _ => {}
+            }
+        }
+        None
     }
 }
 
-pub type RouteHandler = fn(request: Request, response: Response);
+impl Debug for Route {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Route")
+            .field("path", &self.path)
+            .field("name", &self.name)
+            .field("methods", &self.handlers.keys().collect::<Vec<_>>())
+            .field("websocket", &self.websocket.is_some())
+            .field("fallback", &self.fallback.is_some())
+            .field("error_handler", &self.error_handler.is_some())
+            .field("children", &self.children)
+            .field("middlewares", &self.middlewares)
+            .field("excluded_middlewares", &self.excluded_middlewares)
+            .finish()
+    }
+}
+
+pub type RouteHandler = Box<dyn Fn(Request, &mut Response) + Send + Sync + 'static>;
+pub type WebSocketHandler = Box<dyn Fn(Request, WebSocket) + Send + Sync + 'static>;
+pub type ErrorHandler = Box<dyn Fn(&HttpError, &mut Response) + Send + Sync + 'static>;
+
+fn into_route_handler<H: Handler<M>, M>(handler: H) -> RouteHandler {
+    Box::new(move |request, response| handler.call(request, response))
+}
 
 impl Router {
     #[must_use]
     pub fn new() -> Router {
         let mut registered_middlewares: HashMap<String, Middleware> = HashMap::new();
         let session_driver = var("SESSION_DRIVER").unwrap_or_default();
-        let mut middlewares = HashSet::new();
+        let mut middlewares = vec![];
         if session_driver == "file" {
-            registered_middlewares.insert("session".to_string(), session_handler);
-            middlewares.insert("session".to_string());
+            registered_middlewares.insert(
+                "session".to_string(),
+                Middleware::Before(Box::new(session_handler)),
+            );
+            middlewares.push("session".to_string());
         }
         Router {
             registered_middlewares,
+            global_middlewares: vec![],
             prefix: String::new(),
             middlewares,
-            routes: Box::new(Route {
-                path: String::new(),
-                handlers: HashMap::new(),
-                children: vec![],
-                middlewares: HashSet::new(),
-            }),
+            excluded_middlewares: vec![],
+            state: Arc::default(),
+            urls: OnceLock::new(),
+            routes: Box::new(Route::new("", String::new())),
         }
     }
 
@@ -90,50 +492,131 @@ impl Router {
         self
     }
 
+    /// Registers a value shared with every handler, fetched by type through `Request::state`.
+    pub fn with_state<T: Send + Sync + 'static>(&mut self, state: T) -> &mut Self {
+        Arc::make_mut(&mut self.state).insert(state);
+        self
+    }
+
+    /// Adds middlewares to the routes registered on this router from now on.
+    /// Middlewares run in registration order, duplicates are ignored.
     pub fn with_middlewares(
         &mut self,
         middlewares: impl IntoIterator<Item = impl AsRef<str>>,
     ) -> &mut Self {
-        let middlewares: HashSet<String> = middlewares
-            .into_iter()
-            .map(|s| s.as_ref().to_string())
-            .collect();
-        self.middlewares.extend(middlewares);
+        push_middlewares(&mut self.middlewares, middlewares);
+        self
+    }
+
+    /// Adds middlewares that run for every request, before any route or group
+    /// middleware, including requests for public files and unknown paths.
+    pub fn with_global_middlewares(
+        &mut self,
+        middlewares: impl IntoIterator<Item = impl AsRef<str>>,
+    ) -> &mut Self {
+        push_middlewares(&mut self.global_middlewares, middlewares);
         self
     }
 
-    pub fn get(&mut self, path: &str, handler: RouteHandler) -> &mut Box<Route> {
+    /// Skips inherited middlewares for the routes registered on this router from now on.
+    pub fn without_middlewares(
+        &mut self,
+        middlewares: impl IntoIterator<Item = impl AsRef<str>>,
+    ) -> &mut Self {
+        push_middlewares(&mut self.excluded_middlewares, middlewares);
+        self
+    }
+
+    pub fn get<H, M>(&mut self, path: &str, handler: H) -> &mut Box<Route>
+    where
+        H: Handler<M>,
+    {
         self.register(path, HTTPMethod::GET, handler)
     }
 
-    pub fn post(&mut self, path: &str, handler: RouteHandler) -> &mut Box<Route> {
+    pub fn post<H, M>(&mut self, path: &str, handler: H) -> &mut Box<Route>
+    where
+        H: Handler<M>,
+    {
         self.register(path, HTTPMethod::POST, handler)
     }
-    pub fn delete(&mut self, path: &str, handler: RouteHandler) -> &mut Box<Route> {
+    pub fn delete<H, M>(&mut self, path: &str, handler: H) -> &mut Box<Route>
+    where
+        H: Handler<M>,
+    {
         self.register(path, HTTPMethod::DELETE, handler)
     }
-    pub fn put(&mut self, path: &str, handler: RouteHandler) -> &mut Box<Route> {
+    pub fn put<H, M>(&mut self, path: &str, handler: H) -> &mut Box<Route>
+    where
+        H: Handler<M>,
+    {
         self.register(path, HTTPMethod::PUT, handler)
     }
-    pub fn patch(&mut self, path: &str, handler: RouteHandler) -> &mut Box<Route> {
+    pub fn patch<H, M>(&mut self, path: &str, handler: H) -> &mut Box<Route>
+    where
+        H: Handler<M>,
+    {
         self.register(path, HTTPMethod::PATCH, handler)
     }
 
+    /// Registers a WebSocket endpoint. GET requests asking for an upgrade on
+    /// `path` complete the handshake and hand the socket to `handler`, which
+    /// runs on a thread of its own from then on, outside of the worker pool.
+    pub fn websocket<F>(&mut self, path: &str, handler: F) -> &mut Box<Route>
+    where
+        F: Fn(Request, WebSocket) + Send + Sync + 'static,
+    {
+        let route = self.route_mut(path);
+        route.websocket = Some(Box::new(handler));
+        route
+    }
+
     pub fn group<T: AsRef<str>, F: FnOnce(&mut Router)>(&mut self, path: T, configure: F) {
         let prefix = cleanup_path(format!("{}/{}", self.prefix, path.as_ref()));
         let mut sub_router = Router::new();
-        let middlewares = self.middlewares.clone();
-        sub_router.with_prefix(prefix).with_middlewares(middlewares);
+        // Group routes run the parent middlewares first, then the group's own
+        sub_router.middlewares = self.middlewares.clone();
+        sub_router.excluded_middlewares = self.excluded_middlewares.clone();
+        sub_router.with_prefix(prefix);
         configure(&mut sub_router);
+        self.urls.take();
         self.routes.extend(sub_router.routes);
+        for (name, middleware) in sub_router.registered_middlewares {
+            self.registered_middlewares
+                .entry(name)
+                .or_insert(middleware);
+        }
+        push_middlewares(&mut self.global_middlewares, sub_router.global_middlewares);
+        let state = Arc::unwrap_or_clone(sub_router.state);
+        Arc::make_mut(&mut self.state).extend(state);
     }
 
-    pub fn register(
-        &mut self,
-        path: &str,
-        method: HTTPMethod,
-        handler: RouteHandler,
-    ) -> &mut Box<Route> {
+    /// Registers `handler` for `method` requests to `path`. Handlers can return
+    /// anything implementing [`IntoResponse`](crate::response::IntoResponse), such
+    /// as a `Result` whose error implements [`ResponseError`](crate::error::ResponseError).
+    pub fn register<H, M>(&mut self, path: &str, method: HTTPMethod, handler: H) -> &mut Box<Route>
+    where
+        H: Handler<M>,
+    {
+        let route = self.route_mut(path);
+        route.handlers.insert(method, into_route_handler(handler));
+        route
+    }
+
+    /// Finds the route for `path` below the router prefix, creating it when missing.
+    fn route_mut(&mut self, path: &str) -> &mut Box<Route> {
+        let middlewares = self.middlewares.clone();
+        let excluded_middlewares = self.excluded_middlewares.clone();
+        let route_path = self.node_mut(path);
+        push_middlewares(&mut route_path.middlewares, middlewares);
+        push_middlewares(&mut route_path.excluded_middlewares, excluded_middlewares);
+        route_path
+    }
+
+    /// Like `route_mut`, without adding the router middlewares to the route.
+    fn node_mut(&mut self, path: &str) -> &mut Box<Route> {
+        // The returned route may be named, so the URLs are collected again
+        self.urls.take();
         let prefix = cleanup_path(&self.prefix);
         let clean_path = cleanup_path(path);
         let path = format!(
@@ -152,6 +635,11 @@ impl Router {
                 if dir.is_empty() {
                     continue;
                 }
+                assert!(
+                    !matches!(route_path.segment, Segment::CatchAll(_)),
+                    "Catch-all params must be the last segment of a route: {}",
+                    path
+                );
                 let existing_index = route_path
                     .children
                     .iter()
@@ -160,86 +648,623 @@ impl Router {
                 if let Some(index) = existing_index {
                     route_path = route_path.children.get_mut(index).unwrap();
                 } else {
-                    let all_middlewares = self.middlewares.clone();
-                    route_path.children.push(Box::new(Route {
-                        path: dir.to_string(),
-                        children: vec![],
-                        middlewares: all_middlewares,
-                        handlers: HashMap::new(),
-                    }));
+                    let pattern = format!("{}/{}", route_path.pattern, dir);
+                    route_path.children.push(Box::new(Route::new(dir, pattern)));
 
                     route_path = route_path.children.last_mut().unwrap();
                 }
             }
         }
-        route_path.handlers.insert(method, handler);
         route_path
     }
 
-    pub fn register_middleware<T: Into<String>>(&mut self, name: T, handler: Middleware) {
-        self.registered_middlewares.insert(name.into(), handler);
+    /// Handles the requests below the router prefix that match no route nor
+    /// public file, instead of answering `404 Not Found`. Groups can set their own.
+    pub fn fallback<H, M>(&mut self, handler: H) -> &mut Self
+    where
+        H: Handler<M>,
+    {
+        self.node_mut("").fallback = Some(into_route_handler(handler));
+        self
+    }
+
+    /// Renders the errors of requests below the router prefix, such as unknown
+    /// routes or unsupported methods. Groups can set their own.
+    pub fn on_error<F>(&mut self, handler: F) -> &mut Self
+    where
+        F: Fn(&HttpError, &mut Response) + Send + Sync + 'static,
+    {
+        self.node_mut("").error_handler = Some(Box::new(handler));
+        self
+    }
+
+    /// Generates the URL of the route called `name`, see [`UrlGenerator::url`].
+    pub fn url<I, K, V>(&self, name: &str, params: I) -> Result<String, UrlError>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<str>,
+        V: Display,
+    {
+        self.url_generator().url(name, params)
+    }
+
+    /// The named routes, collected on first use and again after routes change.
+    /// Panics when two routes have the same name.
+    pub(crate) fn url_generator(&self) -> &Arc<UrlGenerator> {
+        self.urls.get_or_init(|| {
+            let mut urls = UrlGenerator::default();
+            self.routes.collect_names(&mut urls);
+            Arc::new(urls)
+        })
+    }
+
+    pub fn register_middleware<T, F>(&mut self, name: T, handler: F)
+    where
+        T: Into<String>,
+        F: Fn(&Request, &mut Response) -> Flow + Send + Sync + 'static,
+    {
+        self.registered_middlewares
+            .insert(name.into(), Middleware::Before(Box::new(handler)));
+    }
+
+    /// Registers a middleware wrapping the rest of the chain. It receives the
+    /// request together with a [`Next`] continuation and can work on the
+    /// response after the handler ran.
+    pub fn register_around_middleware<T, F>(&mut self, name: T, handler: F)
+    where
+        T: Into<String>,
+        F: Fn(Request, &mut Response, Next) + Send + Sync + 'static,
+    {
+        self.registered_middlewares
+            .insert(name.into(), Middleware::Around(Box::new(handler)));
     }
 
     pub fn invoke(&self, mut request: Request, mut response: Response) {
+        // Segments are decoded once, so route matching, constraints and params
+        // all see the same values, and `%2F` stays within its segment
         let path = cleanup_path(&request.path);
+        let decoded: Vec<String> = path
+            .split('/')
+            .filter(|dir| !dir.is_empty())
+            .map(|dir| decode(dir).map_or_else(|_| dir.to_owned(), |dir| dir.into_owned()))
+            .collect();
+        let segments: Vec<&str> = decoded.iter().map(String::as_str).collect();
+
+        let mut route_params = HashMap::new();
+        let route = self.routes.find(
+            &segments,
+            &|route| route.accepts(&request),
+            &mut route_params,
+        );
+        let (route, method_allowed) = match route {
+            Some(route) => (Some(route), true),
+            // The path may exist with handlers for other methods
+            None => {
+                let route =
+                    self.routes
+                        .find(&segments, &|route| route.has_handlers(), &mut route_params);
+                (route, false)
+            }
+        };
+        request.route_params = route_params;
+        request.state = Arc::clone(&self.state);
+        request.urls = Arc::clone(self.url_generator());
 
-        let mut current_path = &self.routes;
-        let mut found = true;
-        let mut was_wildcard = false;
-        let mut dyn_route_params: HashMap<String, String> = HashMap::new();
+        let target = format!("{} {}", request.method, request.path);
+        let dispatched = panic::catch_unwind(AssertUnwindSafe(|| {
+            self.dispatch(&segments, route, method_allowed, request, &mut response)
+        }));
+        if let Err(payload) = dispatched {
+            let _ = log(format!(
+                "Panic while handling {}: {}",
+                target,
+                panic_message(&*payload)
+            ));
+            self.recover(&segments, &mut response);
+        }
+        let _ = response.send();
+    }
 
-        if path.len() > 0 {
-            let param_reg = Regex::new(r"\{(\w+)\}").unwrap();
-            for dir in path.split('/') {
-                let existing_index = current_path.children.iter().position(|child| {
-                    child.path == dir || child.path == "*" || param_reg.is_match(&child.path)
-                });
+    /// Runs the handler for the request, or the matching error or fallback.
+    fn dispatch(
+        &self,
+        segments: &[&str],
+        route: Option<&Route>,
+        method_allowed: bool,
+        request: Request,
+        response: &mut Response,
+    ) {
+        let Some(route) = route else {
+            let middlewares = self.resolve_middlewares(&self.global_middlewares, &[]);
+            Next::new(&middlewares, &|request, response| {
+                self.serve_unmatched(segments, request, response)
+            })
+            .run(request, response);
+            return;
+        };
 
-                if let Some(index) = existing_index {
-                    current_path = current_path.children.get(index).unwrap();
-                    if current_path.path == "*" {
-                        was_wildcard = true
-                    } else if param_reg.is_match(&current_path.path) {
-                        dyn_route_params
-                            .insert(strip_braces(&current_path.path).to_owned(), dir.to_owned());
-                    }
-                } else {
-                    if !was_wildcard {
-                        found = false;
-                    }
-                    break;
-                }
-            }
+        let websocket = route
+            .websocket
+            .as_ref()
+            .filter(|_| request.is_websocket_upgrade());
+        let handler = route.handlers.get(&request.method).or_else(|| {
+            (request.method == HTTPMethod::HEAD)
+                .then(|| route.handlers.get(&HTTPMethod::GET))
+                .flatten()
+        });
+        let needs_upgrade = route.websocket.is_some() && request.method == HTTPMethod::GET;
+        if !method_allowed && needs_upgrade {
+            // A WebSocket route accepts GET, but only to upgrade the connection
+            self.run_chain(segments, route, request, response, &|_, response| {
+                response.with_header("Upgrade", "websocket");
+                self.respond_error(segments, HTTPStatus::UPGRADE_REQUIRED.into(), response);
+            });
+        } else if !method_allowed {
+            self.run_chain(segments, route, request, response, &|_, response| {
+                response.with_header("Allow", route.allowed_methods());
+                self.respond_error(segments, HTTPStatus::METHOD_NOT_ALLOWED.into(), response);
+            });
+        } else if let Some(handler) = websocket {
+            self.run_chain(segments, route, request, response, &|request, response| {
+                self.upgrade(segments, request, response, handler)
+            });
+        } else if let Some(handler) = handler {
+            self.run_chain(segments, route, request, response, handler);
+        } else {
+            self.run_chain(segments, route, request, response, &|_, response| {
+                response
+                    .with_status(HTTPStatus::NO_CONTENT)
+                    .with_header("Allow", route.allowed_methods());
+            });
         }
+    }
 
-        request.route_params = dyn_route_params;
+    /// Replaces the response of a request whose handler panicked with a
+    /// `500 Internal Server Error`, unless it was already sent.
+    fn recover(&self, segments: &[&str], response: &mut Response) {
+        if response.is_sent() {
+            return;
+        }
+        response.clear();
+        let rendered = panic::catch_unwind(AssertUnwindSafe(|| {
+            self.respond_error(segments, HTTPStatus::INTERNAL_SERVER_ERROR.into(), response)
+        }));
+        if rendered.is_err() {
+            let _ = log("Panic while rendering an error");
+            response
+                .clear()
+                .with_status(HTTPStatus::INTERNAL_SERVER_ERROR)
+                .text("Internal Server Error");
+        }
+    }
 
-        if found && !current_path.handlers.contains_key(&request.method) {
-            let has_wildcard = current_path
-                .children
-                .iter()
-                .position(|child| child.path == "*");
+    /// Runs the middlewares of `route` around `handler`.
+    fn run_chain(
+        &self,
+        segments: &[&str],
+        route: &Route,
+        request: Request,
+        response: &mut Response,
+        handler: &dyn Fn(Request, &mut Response),
+    ) {
+        let names: Vec<&String> = self
+            .global_middlewares
+            .iter()
+            .chain(&route.middlewares)
+            .collect();
+        let middlewares = self.resolve_middlewares(names, &route.excluded_middlewares);
+        Next::new(&middlewares, &|request, response| {
+            self.call_handler(segments, handler, request, response)
+        })
+        .run(request, response);
+    }
 
-            if let Some(index) = has_wildcard {
-                current_path = current_path.children.get(index).unwrap()
-            }
+    /// Runs `handler`, then renders the error it answered with, if any.
+    fn call_handler(
+        &self,
+        segments: &[&str],
+        handler: &dyn Fn(Request, &mut Response),
+        request: Request,
+        response: &mut Response,
+    ) {
+        handler(request, response);
+        if let Some(error) = response.take_error() {
+            self.respond_error(segments, error, response);
         }
+    }
 
-        if found && (current_path.handlers.contains_key(&request.method)) {
-            for middleware in &current_path.middlewares {
-                if let Some(handler) = self.registered_middlewares.get(middleware) {
-                    handler(&request, &mut response);
-                }
+    /// Looks up the registered middlewares for `names`, skipping duplicates and `excluded`.
+    fn resolve_middlewares<'a>(
+        &'a self,
+        names: impl IntoIterator<Item = &'a String>,
+        excluded: &[String],
+    ) -> Vec<&'a Middleware> {
+        let mut seen: Vec<&String> = vec![];
+        let mut middlewares = vec![];
+        for name in names {
+            if seen.contains(&name) || excluded.contains(name) {
+                continue;
+            }
+            seen.push(name);
+            if let Some(middleware) = self.registered_middlewares.get(name) {
+                middlewares.push(middleware);
             }
-            current_path.handlers.get(&request.method).unwrap()(request, response);
-        } else {
-            self.try_serve_public(request, response);
         }
+        middlewares
     }
 
-    fn try_serve_public(&self, request: Request, mut response: Response) {
+    fn upgrade(
+        &self,
+        segments: &[&str],
+        request: Request,
+        response: &mut Response,
+        handler: &WebSocketHandler,
+    ) {
+        if request.header("Sec-WebSocket-Version").map(|v| v.trim()) != Some("13") {
+            response.with_header("Sec-WebSocket-Version", "13");
+            let error = HttpError::new(HTTPStatus::BAD_REQUEST, "Unsupported WebSocket version");
+            self.respond_error(segments, error, response);
+            return;
+        }
+        let Some(key) = request.header("Sec-WebSocket-Key").cloned() else {
+            let error = HttpError::new(HTTPStatus::BAD_REQUEST, "Missing Sec-WebSocket-Key");
+            self.respond_error(segments, error, response);
+            return;
+        };
+        // The socket may stay open for long, so it doesn't hold up a worker
+        if !pool::detach() {
+            let error = HttpError::new(
+                HTTPStatus::SERVICE_UNAVAILABLE,
+                "Too many open WebSocket connections",
+            );
+            self.respond_error(segments, error, response);
+            return;
+        }
+        if let Ok(socket) = response.upgrade(&key) {
+            handler(request, socket);
+        }
+    }
+
+    /// Serves a public file for requests matching no route, falling back to the
+    /// nearest fallback handler, or a `404 Not Found` error.
+    fn serve_unmatched(&self, segments: &[&str], request: Request, response: &mut Response) {
         let mut public_path = cleanup_path(var("APP_PUBLIC_DIR").unwrap_or("public".to_string()));
         public_path.push_str(&request.path);
-        response.file(Path::new(&public_path));
+        let public_path = Path::new(&public_path);
+        if public_path.is_file() {
+            response.file(public_path);
+            return;
+        }
+        // The nearest group with a fallback or an error handler decides, so a group
+        // rendering its own errors isn't answered by a fallback set higher up
+        let nearest = self.routes.find_nearest(segments, &|route| {
+            (route.fallback.is_some() || route.error_handler.is_some()).then_some(route)
+        });
+        if let Some(fallback) = nearest.and_then(|route| route.fallback.as_ref()) {
+            self.call_handler(segments, fallback, request, response);
+        } else {
+            self.respond_error(segments, HTTPStatus::NOT_FOUND.into(), response);
+        }
+    }
+
+    /// Renders `error` with the nearest error handler, or as plain text.
+    fn respond_error(&self, segments: &[&str], error: HttpError, response: &mut Response) {
+        let handler = self
+            .routes
+            .find_nearest(segments, &|route| route.error_handler.as_deref());
+        match handler {
+            Some(handler) => handler(&error, response),
+            None => {
+                response.with_status(error.status).text(error.message);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Cursor, Read},
+        net::{TcpListener, TcpStream},
+    };
+
+    use super::*;
+    use crate::request::RequestLimits;
+
+    fn router(paths: &[&str]) -> Router {
+        let mut router = Router::new();
+        for path in paths {
+            router.get(path, || ());
+        }
+        router
+    }
+
+    /// The pattern of the route matching `path`, with the params it captured.
+    fn find(router: &Router, path: &str) -> Option<(String, HashMap<String, String>)> {
+        let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+        let mut params = HashMap::new();
+        let route = router
+            .routes
+            .find(&segments, &|route| route.has_handlers(), &mut params)?;
+        Some((route.pattern.clone(), params))
+    }
+
+    fn params(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    /// Sends `request` through the router, returning the raw response.
+    fn send(router: &Router, request: &str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let request = Request::parse(
+            &mut Cursor::new(request.as_bytes()),
+            &RequestLimits::default(),
+        )
+        .unwrap();
+        let mut response = Response::new(stream);
+        response.with_omit_body(request.method == HTTPMethod::HEAD);
+        router.invoke(request, response);
+        let mut raw = String::new();
+        client.read_to_string(&mut raw).unwrap();
+        raw
+    }
+
+    fn header<'a>(raw: &'a str, name: &str) -> Option<&'a str> {
+        raw.lines()
+            .filter_map(|line| line.split_once(": "))
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value)
+    }
+
+    /// A middleware appending `name` to the `X-Trace` header.
+    fn trace(name: &'static str) -> impl Fn(&Request, &mut Response) -> Flow + Send + Sync {
+        move |_, response| {
+            let trace = match response.header("X-Trace") {
+                Some(trace) => format!("{},{}", trace, name),
+                None => name.to_owned(),
+            };
+            response.with_header("X-Trace", trace);
+            Flow::Continue
+        }
+    }
+
+    fn traced_router() -> Router {
+        let mut router = Router::new();
+        for name in ["global", "router", "group", "route"] {
+            router.register_middleware(name, trace(name));
+        }
+        router.with_global_middlewares(["global"]);
+        router.with_middlewares(["router"]);
+        router
+    }
+
+    #[test]
+    fn static_segments_win_over_params_whatever_the_order() {
+        let router = router(&["/users/{id}", "/users/me"]);
+        assert_eq!(
+            find(&router, "/users/me"),
+            Some(("/users/me".into(), params(&[])))
+        );
+        assert_eq!(
+            find(&router, "/users/42"),
+            Some(("/users/{id}".into(), params(&[("id", "42")])))
+        );
+    }
+
+    #[test]
+    fn dead_end_static_branch_backtracks_to_params() {
+        let router = router(&["/users/me", "/users/{id}/posts"]);
+        assert_eq!(
+            find(&router, "/users/me/posts"),
+            Some(("/users/{id}/posts".into(), params(&[("id", "me")])))
+        );
+    }
+
+    #[test]
+    fn params_of_abandoned_branches_are_removed() {
+        let router = router(&["/a/{x:int}/b", "/a/{y}/c"]);
+        assert_eq!(
+            find(&router, "/a/1/c"),
+            Some(("/a/{y}/c".into(), params(&[("y", "1")])))
+        );
+        assert_eq!(find(&router, "/a/1/d"), None);
+    }
+
+    #[test]
+    fn wildcards_come_after_params() {
+        let router = router(&["/files/*", "/files/{name}"]);
+        assert_eq!(
+            find(&router, "/files/a"),
+            Some(("/files/{name}".into(), params(&[("name", "a")])))
+        );
+        assert_eq!(
+            find(&router, "/files/a/b"),
+            Some(("/files/*".into(), params(&[])))
+        );
+        assert_eq!(
+            find(&router, "/files"),
+            Some(("/files/*".into(), params(&[])))
+        );
+    }
+
+    #[test]
+    fn constrained_params_are_tried_before_plain_ones() {
+        let router = router(&["/posts/{slug}", "/posts/{id:int}"]);
+        assert_eq!(
+            find(&router, "/posts/12"),
+            Some(("/posts/{id:int}".into(), params(&[("id", "12")])))
+        );
+        assert_eq!(
+            find(&router, "/posts/hello"),
+            Some(("/posts/{slug}".into(), params(&[("slug", "hello")])))
+        );
+    }
+
+    #[test]
+    fn uuid_params_only_match_hyphenated_uuids() {
+        let router = router(&["/orders/{id:uuid}"]);
+        assert_eq!(
+            find(&router, "/orders/67e55044-10b1-426f-9247-bb680e5fe0c8"),
+            Some((
+                "/orders/{id:uuid}".into(),
+                params(&[("id", "67e55044-10b1-426f-9247-bb680e5fe0c8")])
+            ))
+        );
+        for path in [
+            "/orders/67e5504410b1426f9247bb680e5fe0c8",
+            "/orders/67e55044-10b1-426f-9247-bb680e5fe0cz",
+            "/orders/67e55044-10b1-426f-9247-bb680e5fe0c",
+        ] {
+            assert_eq!(find(&router, path), None, "{}", path);
+        }
+    }
+
+    #[test]
+    fn regex_constraints_must_match_the_whole_segment() {
+        let router = router(&["/archive/{year:[0-9]{4}}", "/archive/{slug}"]);
+        assert_eq!(
+            find(&router, "/archive/2024"),
+            Some((
+                "/archive/{year:[0-9]{4}}".into(),
+                params(&[("year", "2024")])
+            ))
+        );
+        assert_eq!(
+            find(&router, "/archive/20245"),
+            Some(("/archive/{slug}".into(), params(&[("slug", "20245")])))
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid route param pattern")]
+    fn invalid_regex_constraints_are_rejected() {
+        router(&["/archive/{year:[0-9}"]);
+    }
+
+    #[test]
+    fn catch_all_params_capture_the_rest_of_the_path() {
+        let router = router(&["/docs/{*path}"]);
+        assert_eq!(
+            find(&router, "/docs/guide/intro"),
+            Some(("/docs/{*path}".into(), params(&[("path", "guide/intro")])))
+        );
+        assert_eq!(
+            find(&router, "/docs"),
+            Some(("/docs/{*path}".into(), params(&[("path", "")])))
+        );
+    }
+
+    #[test]
+    fn optional_params_may_be_left_out() {
+        let router = router(&["/pages/{page?:int}"]);
+        assert_eq!(
+            find(&router, "/pages/2"),
+            Some(("/pages/{page?:int}".into(), params(&[("page", "2")])))
+        );
+        assert_eq!(
+            find(&router, "/pages"),
+            Some(("/pages/{page?:int}".into(), params(&[])))
+        );
+        assert_eq!(find(&router, "/pages/two"), None);
+    }
+
+    #[test]
+    fn middlewares_run_from_global_to_route_once_each() {
+        let mut router = traced_router();
+        router.group("/admin", |admin| {
+            admin.with_middlewares(["group", "router"]);
+            admin
+                .get("/users", || "users")
+                .with_middlewares(["route", "group", "global"]);
+        });
+        let response = send(&router, "GET /admin/users HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert_eq!(
+            header(&response, "X-Trace"),
+            Some("global,router,group,route")
+        );
+    }
+
+    #[test]
+    fn routes_can_skip_inherited_and_global_middlewares() {
+        let mut router = traced_router();
+        router.group("/admin", |admin| {
+            admin.with_middlewares(["group"]);
+            admin.get("/users", || "users");
+            admin
+                .get("/login", || "login")
+                .without_middlewares(["group", "global"]);
+        });
+        let response = send(&router, "GET /admin/users HTTP/1.1\r\n\r\n");
+        assert_eq!(header(&response, "X-Trace"), Some("global,router,group"));
+        let response = send(&router, "GET /admin/login HTTP/1.1\r\n\r\n");
+        assert_eq!(header(&response, "X-Trace"), Some("router"));
+    }
+
+    #[test]
+    fn stopping_middlewares_skip_the_rest_of_the_chain() {
+        let mut router = traced_router();
+        router.register_middleware("auth", |_: &Request, response: &mut Response| {
+            response
+                .with_status(HTTPStatus::UNAUTHORIZED)
+                .text("Unauthorized");
+            Flow::Stop
+        });
+        router
+            .get("/private", |response: &mut Response| {
+                response.text("secret");
+            })
+            .with_middlewares(["auth", "route"]);
+        let response = send(&router, "GET /private HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 401 Unauthorized"));
+        assert!(response.ends_with("Unauthorized"));
+        assert_eq!(header(&response, "X-Trace"), Some("global,router"));
+    }
+
+    #[test]
+    fn unhandled_methods_list_the_allowed_ones() {
+        let mut router = Router::new();
+        router.post("/users", || "created");
+        let response = send(&router, "GET /users HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 405 Method Not Allowed"));
+        assert_eq!(header(&response, "Allow"), Some("POST, OPTIONS"));
+    }
+
+    #[test]
+    fn head_requests_need_a_get_handler() {
+        let mut router = Router::new();
+        router.get("/users", || "users");
+        router.post("/posts", || "created");
+        let response = send(&router, "HEAD /users HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert_eq!(header(&response, "Content-Length"), Some("5"));
+        assert!(response.ends_with("\r\n\r\n"));
+
+        let response = send(&router, "HEAD /posts HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 405 Method Not Allowed"));
+    }
+
+    #[test]
+    fn options_requests_list_the_allowed_methods() {
+        let mut router = Router::new();
+        router.get("/users", || "users");
+        router.post("/users", || "created");
+        let response = send(&router, "OPTIONS /users HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 204 No Content"));
+        assert_eq!(header(&response, "Allow"), Some("GET, HEAD, POST, OPTIONS"));
+        assert!(response.ends_with("\r\n\r\n"));
+    }
+
+    #[test]
+    fn plain_requests_to_websocket_routes_need_an_upgrade() {
+        let mut router = Router::new();
+        router.websocket("/chat", |_, _| {});
+        let response = send(&router, "GET /chat HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 426 Upgrade Required"));
     }
 }
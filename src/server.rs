@@ -1,62 +1,397 @@
-use std::{env::var, error::Error, io::BufReader, net::TcpListener, ops::DerefMut};
+use std::{
+    env::var,
+    error::Error,
+    io::{self, BufRead, BufReader, ErrorKind, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream},
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
 
 use crate::{
     logging::log,
-    request::Request,
+    pool::ThreadPool,
+    request::{Request, RequestLimits},
+    router::panic_message,
     session::{CookieSession, FileSession, NoSession, SessionBackend, SessionStore},
+    signal,
     utils::uuid,
+    HTTPMethod, HTTPStatus,
 };
 
-use super::{
-    response::Response,
-    router::{RouteHandler, Router},
-    HTTPMethod,
-};
+use super::{response::Response, router::Router};
 
 pub struct Server {
     port: String,
+    threads: usize,
+    max_long_lived: usize,
+    options: ConnectionOptions,
+    shutdown: Arc<AtomicBool>,
+    shutdown_timeout: Duration,
     pub router: Router,
 }
+
+/// Stops a running [`Server`] from another thread.
+#[derive(Debug, Clone)]
+pub struct ShutdownHandle {
+    shutdown: Arc<AtomicBool>,
+}
+
+impl ShutdownHandle {
+    /// Makes `listen` stop accepting connections, drain in-flight requests and return.
+    pub fn shutdown(&self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+    }
+}
+
+#[derive(Debug, Clone)]
+struct ConnectionOptions {
+    keep_alive_timeout: Duration,
+    max_requests: usize,
+    limits: RequestLimits,
+}
 impl Server {
     pub fn new<T: Into<String>>(url: T) -> Server {
         return Server {
             port: url.into(),
+            threads: default_threads(),
+            max_long_lived: 1024,
+            options: ConnectionOptions {
+                keep_alive_timeout: Duration::from_secs(5),
+                max_requests: 100,
+                limits: RequestLimits::default(),
+            },
+            shutdown: Arc::new(AtomicBool::new(false)),
+            shutdown_timeout: Duration::from_secs(30),
             router: Router::new(),
         };
     }
 
-    pub fn listen(&self) -> Result<(), Box<dyn Error>> {
+    /// Sets the number of worker threads used to serve connections.
+    /// Defaults to `APP_THREADS`, or the available parallelism of the machine.
+    pub fn with_threads(&mut self, threads: usize) -> &mut Self {
+        assert!(threads > 0, "Server needs at least one worker thread");
+        self.threads = threads;
+        self
+    }
+
+    /// Sets how many WebSocket and Server-Sent Events connections may stay open
+    /// at once, each on a thread of its own. Past the limit, WebSocket upgrades
+    /// are answered with `503 Service Unavailable` and event streams keep their
+    /// worker thread. Defaults to 1024.
+    pub fn with_max_long_lived_connections(&mut self, max: usize) -> &mut Self {
+        self.max_long_lived = max;
+        self
+    }
+
+    /// Registers a value shared with every handler, fetched by type through `Request::state`.
+    pub fn with_state<T: Send + Sync + 'static>(&mut self, state: T) -> &mut Self {
+        self.router.with_state(state);
+        self
+    }
+
+    /// Sets how long an idle persistent connection is kept open while waiting
+    /// for the next request. Defaults to 5 seconds.
+    pub fn with_keep_alive_timeout(&mut self, timeout: Duration) -> &mut Self {
+        assert!(!timeout.is_zero(), "Keep-alive timeout must be non-zero");
+        self.options.keep_alive_timeout = timeout;
+        self
+    }
+
+    /// Sets how many requests are served on a single connection before it is
+    /// closed. Defaults to 100; `1` disables keep-alive.
+    pub fn with_max_requests(&mut self, max_requests: usize) -> &mut Self {
+        assert!(
+            max_requests > 0,
+            "Connections must serve at least one request"
+        );
+        self.options.max_requests = max_requests;
+        self
+    }
+
+    /// Sets the maximum size in bytes of a request line and its headers.
+    /// Larger requests are rejected with `431 Request Header Fields Too Large`.
+    pub fn with_max_header_size(&mut self, bytes: usize) -> &mut Self {
+        self.options.limits.max_header_size = bytes;
+        self
+    }
+
+    /// Sets the maximum size in bytes of a request body.
+    /// Larger requests are rejected with `413 Payload Too Large`.
+    pub fn with_max_body_size(&mut self, bytes: usize) -> &mut Self {
+        self.options.limits.max_body_size = bytes;
+        self
+    }
+
+    /// Sets how long `listen` waits for in-flight requests to finish once a
+    /// shutdown was requested. Defaults to 30 seconds.
+    pub fn with_shutdown_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.shutdown_timeout = timeout;
+        self
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle {
+            shutdown: Arc::clone(&self.shutdown),
+        }
+    }
+
+    /// Serves connections until a shutdown is requested through a [`ShutdownHandle`],
+    /// SIGINT or SIGTERM, then waits for in-flight requests before returning.
+    pub fn listen(self) -> Result<(), Box<dyn Error>> {
+        // Collects the named routes, so duplicate names fail before serving
+        self.router.url_generator();
         println!("Server started on port {}", self.port);
         let listener = TcpListener::bind(format!("0.0.0.0:{}", self.port))?;
-        // let pool = ThreadPool::new(var("APP_THREADS").unwrap().parse().unwrap());
-        for stream in listener.incoming() {
-            let stream = stream.unwrap();
-            let buf_reader = BufReader::new(&stream);
-            let mut request = Request::parse(buf_reader);
-            let response = Response::new(stream);
-
-            let mut session: SessionBackend;
-            let new_id = uuid();
-            let session_id = request.cookies.get("session_id").unwrap_or(&new_id);
-            let session_driver = var("SESSION_DRIVER").unwrap_or_default();
-            if session_driver == "file" {
-                session = SessionBackend::File(FileSession::new(session_id));
-            } else if session_driver == "cookie" {
-                session = SessionBackend::Cookie(CookieSession::new(session_id));
-            } else {
-                session = SessionBackend::NoSession(NoSession::new());
-            }
-            session.init(&request).unwrap();
-            request.session = session;
-            log(format!("{} request at {}", request.method, request.path))?;
-            self.router.invoke(request, response);
-            // pool.execute(|| {
-            //     let buf_reader = BufReader::new(&stream);
-            //     let request = Request::parse(buf_reader);
-            //     println!("{} request at {}", request.method, request.path);
-            //     self.router.invoke(request, Response::new(stream));
-            // });
+        signal::install();
+
+        let mut pool = ThreadPool::new(self.threads);
+        pool.with_max_detached(self.max_long_lived);
+        let router = Arc::new(self.router);
+        let options = Arc::new(self.options);
+        let shutdown = self.shutdown;
+        watch_shutdown(Arc::clone(&shutdown), listener.local_addr()?);
+        loop {
+            let accepted = listener.accept();
+            // The watcher wakes the blocking accept with a connection of its own
+            if shutdown.load(Ordering::SeqCst) {
+                break;
+            }
+            let stream = match accepted {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    let _ = log(format!("Failed to accept connection: {}", e));
+                    continue;
+                }
+            };
+            let router = Arc::clone(&router);
+            let options = Arc::clone(&options);
+            let shutdown = Arc::clone(&shutdown);
+            pool.execute(move || {
+                if let Err(e) = handle_connection(&router, &options, &shutdown, stream) {
+                    let _ = log(format!("Failed to handle connection: {}", e));
+                }
+            });
         }
+
+        signal::restore();
+        println!("Shutting down, waiting for in-flight requests");
+        drop(listener);
+        let drained = pool.shutdown(self.shutdown_timeout);
+        if !drained {
+            log("Shutdown timeout reached with requests still in flight")?;
+        }
+        log("Server stopped")?;
+        io::stdout().flush()?;
         Ok(())
     }
 }
+
+/// Waits for a shutdown request or a signal, then wakes the accept loop of
+/// `listen` by connecting to the listener at `addr`.
+fn watch_shutdown(shutdown: Arc<AtomicBool>, addr: SocketAddr) {
+    thread::spawn(move || {
+        while !shutdown.load(Ordering::SeqCst) {
+            if signal::received() {
+                shutdown.store(true, Ordering::SeqCst);
+                break;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        let ip = match addr.ip() {
+            IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+            IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+            ip => ip,
+        };
+        let _ = TcpStream::connect((ip, addr.port()));
+    });
+}
+
+fn default_threads() -> usize {
+    var("APP_THREADS")
+        .ok()
+        .and_then(|threads| threads.parse().ok())
+        .filter(|threads| *threads > 0)
+        .unwrap_or_else(|| thread::available_parallelism().map_or(4, |n| n.get()))
+}
+
+/// How often an idle connection checks whether the server is shutting down.
+const IDLE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Waits for the next request on a connection, in short reads so an idle
+/// connection doesn't hold up a shutdown until the keep-alive timeout. Returns
+/// `false` when the client hangs up, stays idle too long or the server stops.
+fn wait_for_request(
+    buf_reader: &mut BufReader<&TcpStream>,
+    stream: &TcpStream,
+    timeout: Duration,
+    shutdown: &AtomicBool,
+) -> io::Result<bool> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        stream.set_read_timeout(Some(
+            remaining.clamp(Duration::from_millis(1), IDLE_POLL_INTERVAL),
+        ))?;
+        match buf_reader.fill_buf() {
+            Ok(buf) => return Ok(!buf.is_empty()),
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                if shutdown.load(Ordering::SeqCst) || Instant::now() >= deadline {
+                    return Ok(false);
+                }
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn handle_connection(
+    router: &Router,
+    options: &ConnectionOptions,
+    shutdown: &AtomicBool,
+    stream: TcpStream,
+) -> Result<(), Box<dyn Error>> {
+    let mut buf_reader = BufReader::new(&stream);
+    let mut served = 0;
+    loop {
+        if !wait_for_request(
+            &mut buf_reader,
+            &stream,
+            options.keep_alive_timeout,
+            shutdown,
+        )? {
+            break;
+        }
+        stream.set_read_timeout(Some(options.keep_alive_timeout))?;
+
+        let parsed = panic::catch_unwind(AssertUnwindSafe(|| {
+            Request::parse(&mut buf_reader, &options.limits)
+        }));
+        let mut request = match parsed {
+            Ok(Ok(request)) => request,
+            Err(payload) => {
+                log(format!(
+                    "Panic while parsing request: {}",
+                    panic_message(&*payload)
+                ))?;
+                let mut response = Response::new(stream.try_clone()?);
+                response
+                    .with_status(HTTPStatus::INTERNAL_SERVER_ERROR)
+                    .text("Internal Server Error");
+                response.send()?;
+                break;
+            }
+            Ok(Err(e)) => {
+                log(format!("Rejected request: {}", e))?;
+                if let Some(status) = e.status() {
+                    // The details stay in the log, as they may echo what the client sent
+                    let mut response = Response::new(stream.try_clone()?);
+                    let message = status.status_text().to_owned();
+                    response.with_status(status).text(message);
+                    response.send()?;
+                }
+                break;
+            }
+        };
+        served += 1;
+        let keep_alive = request.keep_alive()
+            && !request.is_websocket_upgrade()
+            && served < options.max_requests
+            && !shutdown.load(Ordering::SeqCst);
+        let mut response = Response::new(stream.try_clone()?);
+        response
+            .with_keep_alive(keep_alive)
+            .with_omit_body(request.method == HTTPMethod::HEAD);
+
+        log(format!("{} request at {}", request.method, request.path))?;
+        match init_session(&request) {
+            Ok(session) => {
+                request.session = session;
+                router.invoke(request, response);
+            }
+            Err(e) => {
+                log(format!("Failed to start session: {}", e))?;
+                response
+                    .with_status(HTTPStatus::INTERNAL_SERVER_ERROR)
+                    .text("Internal Server Error");
+                response.send()?;
+            }
+        }
+
+        if !keep_alive {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn init_session(request: &Request) -> Result<SessionBackend, Box<dyn Error>> {
+    let mut session: SessionBackend;
+    let new_id = uuid();
+    let session_id = request.cookies.get("session_id").unwrap_or(&new_id);
+    let session_driver = var("SESSION_DRIVER").unwrap_or_default();
+    if session_driver == "file" {
+        session = SessionBackend::File(FileSession::new(session_id));
+    } else if session_driver == "cookie" {
+        session = SessionBackend::Cookie(CookieSession::new(session_id));
+    } else {
+        session = SessionBackend::NoSession(NoSession::new());
+    }
+    session.init(request)?;
+    Ok(session)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Read, sync::mpsc};
+
+    use super::*;
+
+    fn connect(port: u16) -> TcpStream {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            match TcpStream::connect(("127.0.0.1", port)) {
+                Ok(stream) => return stream,
+                Err(e) if Instant::now() >= deadline => panic!("Server didn't start: {}", e),
+                Err(_) => thread::sleep(Duration::from_millis(10)),
+            }
+        }
+    }
+
+    #[test]
+    fn idle_connections_do_not_hold_up_shutdown() {
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let mut server = Server::new(port.to_string());
+        server
+            .with_threads(1)
+            .with_keep_alive_timeout(Duration::from_secs(20));
+        server.router.get("/", || "ok");
+        let handle = server.shutdown_handle();
+        let (stopped, listen_returned) = mpsc::channel();
+        thread::spawn(move || stopped.send(server.listen().is_ok()).unwrap());
+
+        let mut client = connect(port);
+        client
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut response = [0; 1024];
+        let read = client.read(&mut response).unwrap();
+        assert!(response[..read].starts_with(b"HTTP/1.1 200 OK"));
+
+        // The connection is kept open, idle, from here on
+        let start = Instant::now();
+        handle.shutdown();
+        assert!(listen_returned
+            .recv_timeout(Duration::from_secs(5))
+            .unwrap());
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+}
@@ -142,8 +142,9 @@ impl CookieSession {
         let session_val: HashMap<String, Value> = if session_in_cookie.is_empty() {
             HashMap::new()
         } else {
+            // A tampered or outdated cookie starts a new session
             let decoded = decode(&session_in_cookie).unwrap_or_default();
-            from_str(&decoded).unwrap()
+            from_str(&decoded).unwrap_or_default()
         };
         self.data = session_val;
         Ok(())
@@ -0,0 +1,40 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static RECEIVED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+extern "C" fn on_signal(_: libc::c_int) {
+    RECEIVED.store(true, Ordering::SeqCst);
+    // A second signal during the shutdown terminates the process right away
+    restore();
+}
+
+/// Installs SIGINT and SIGTERM handlers that request a graceful shutdown.
+#[cfg(unix)]
+pub fn install() {
+    RECEIVED.store(false, Ordering::SeqCst);
+    let handler = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    unsafe {
+        libc::signal(libc::SIGINT, handler);
+        libc::signal(libc::SIGTERM, handler);
+    }
+}
+
+/// Restores the default SIGINT and SIGTERM behaviour.
+#[cfg(unix)]
+pub fn restore() {
+    unsafe {
+        libc::signal(libc::SIGINT, libc::SIG_DFL);
+        libc::signal(libc::SIGTERM, libc::SIG_DFL);
+    }
+}
+
+#[cfg(not(unix))]
+pub fn install() {}
+
+#[cfg(not(unix))]
+pub fn restore() {}
+
+pub fn received() -> bool {
+    RECEIVED.load(Ordering::SeqCst)
+}
@@ -0,0 +1,99 @@
+use std::{
+    fmt::Display,
+    io::{self, Write},
+};
+
+use crate::stream::ChunkedWriter;
+
+/// A single Server-Sent Event.
+#[derive(Debug, Clone, Default)]
+pub struct Event {
+    id: Option<String>,
+    event: Option<String>,
+    retry: Option<u64>,
+    data: String,
+}
+
+impl Event {
+    pub fn new<T: Into<String>>(data: T) -> Self {
+        Event {
+            data: data.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Sets the event id, which the browser sends back as `Last-Event-ID` when reconnecting.
+    pub fn with_id<T: Into<String>>(mut self, id: T) -> Self {
+        self.id = Some(id.into());
+        self
+    }
+
+    /// Sets the event type, dispatched to `addEventListener(type, ..)` listeners.
+    pub fn with_event<T: Into<String>>(mut self, event: T) -> Self {
+        self.event = Some(event.into());
+        self
+    }
+
+    /// Sets the reconnection delay in milliseconds.
+    pub fn with_retry(mut self, retry: u64) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+}
+
+// Field values must not contain line breaks, they would start a new field
+fn single_line(value: &str) -> String {
+    value.replace(['\r', '\n'], "")
+}
+
+impl Display for Event {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(ref id) = self.id {
+            writeln!(f, "id: {}", single_line(id))?;
+        }
+        if let Some(ref event) = self.event {
+            writeln!(f, "event: {}", single_line(event))?;
+        }
+        if let Some(retry) = self.retry {
+            writeln!(f, "retry: {}", retry)?;
+        }
+        for line in self.data.lines() {
+            writeln!(f, "data: {}", line)?;
+        }
+        if self.data.is_empty() {
+            writeln!(f, "data: ")?;
+        }
+        writeln!(f)
+    }
+}
+
+/// An open `text/event-stream` response. The connection stays open until the
+/// stream is closed or dropped.
+#[derive(Debug)]
+pub struct EventStream<'a> {
+    writer: ChunkedWriter<'a>,
+}
+
+impl<'a> EventStream<'a> {
+    pub(crate) fn new(writer: ChunkedWriter<'a>) -> EventStream<'a> {
+        EventStream { writer }
+    }
+
+    /// Sends an event, failing once the client has disconnected.
+    pub fn send(&mut self, event: Event) -> io::Result<()> {
+        self.writer.write_all(event.to_string().as_bytes())?;
+        self.writer.flush()
+    }
+
+    /// Sends a comment line, ignored by clients but useful to keep idle proxies from
+    /// closing the connection.
+    pub fn comment<T: AsRef<str>>(&mut self, comment: T) -> io::Result<()> {
+        let comment = format!(": {}\n\n", single_line(comment.as_ref()));
+        self.writer.write_all(comment.as_bytes())?;
+        self.writer.flush()
+    }
+
+    pub fn close(self) -> io::Result<()> {
+        self.writer.finish()
+    }
+}
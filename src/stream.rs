@@ -0,0 +1,63 @@
+use std::{
+    io::{self, Write},
+    net::TcpStream,
+};
+
+/// Writes a response body using the chunked transfer coding.
+/// The body is terminated by [`ChunkedWriter::finish`], or when the writer is dropped.
+#[derive(Debug)]
+pub struct ChunkedWriter<'a> {
+    stream: &'a mut TcpStream,
+    finished: bool,
+    /// Set for `HEAD` requests, whose response has no body.
+    omit_body: bool,
+}
+
+impl<'a> ChunkedWriter<'a> {
+    pub(crate) fn new(stream: &'a mut TcpStream, omit_body: bool) -> ChunkedWriter<'a> {
+        ChunkedWriter {
+            stream,
+            finished: omit_body,
+            omit_body,
+        }
+    }
+
+    /// Sends the terminating zero-length chunk.
+    pub fn finish(mut self) -> io::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+        self.stream.write_all(b"0\r\n\r\n")?;
+        self.stream.flush()
+    }
+}
+
+impl Write for ChunkedWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // An empty chunk would end the body early
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.omit_body {
+            return Ok(buf.len());
+        }
+        let mut chunk = format!("{:X}\r\n", buf.len()).into_bytes();
+        chunk.extend_from_slice(buf);
+        chunk.extend_from_slice(b"\r\n");
+        self.stream.write_all(&chunk)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl Drop for ChunkedWriter<'_> {
+    fn drop(&mut self) {
+        if !self.finished {
+            let _ = self.stream.write_all(b"0\r\n\r\n");
+        }
+    }
+}
@@ -0,0 +1,245 @@
+use std::{collections::HashMap, fmt::Display};
+
+use urlencoding::encode;
+
+use crate::router::Segment;
+
+/// Builds URLs for named routes, so links keep working when route paths change.
+#[derive(Debug, Clone, Default)]
+pub struct UrlGenerator {
+    /// The parsed segments of the route patterns, by route name.
+    patterns: HashMap<String, Vec<Segment>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UrlError {
+    /// No route was registered with this name.
+    UnknownRoute(String),
+    MissingParam {
+        route: String,
+        param: String,
+    },
+    /// The value doesn't satisfy the constraint of the param, so the URL would not match the route.
+    InvalidParam {
+        route: String,
+        param: String,
+        value: String,
+    },
+}
+
+impl Display for UrlError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UrlError::UnknownRoute(name) => write!(f, "No route named `{}`", name),
+            UrlError::MissingParam { route, param } => {
+                write!(f, "Missing param `{}` for route `{}`", param, route)
+            }
+            UrlError::InvalidParam {
+                route,
+                param,
+                value,
+            } => write!(
+                f,
+                "Invalid value {:?} for param `{}` of route `{}`",
+                value, param, route
+            ),
+        }
+    }
+}
+
+impl std::error::Error for UrlError {}
+
+impl UrlGenerator {
+    /// Adds a named route. Panics when another route has the same name.
+    pub(crate) fn insert(&mut self, name: String, pattern: &str) {
+        assert!(
+            !self.patterns.contains_key(&name),
+            "Route name `{}` is used by more than one route: {}",
+            name,
+            pattern
+        );
+        let segments = pattern
+            .split('/')
+            .filter(|s| !s.is_empty())
+            .map(Segment::parse)
+            .collect();
+        self.patterns.insert(name, segments);
+    }
+
+    /// Generates the path of the route called `name`. Params are percent-encoded
+    /// into the path, and those the route doesn't use are appended as a query string.
+    /// A `*` wildcard is filled with the `*` param.
+    pub fn url<I, K, V>(&self, name: &str, params: I) -> Result<String, UrlError>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<str>,
+        V: Display,
+    {
+        let segments = self
+            .patterns
+            .get(name)
+            .ok_or_else(|| UrlError::UnknownRoute(name.to_owned()))?;
+        let mut params: Vec<(String, String)> = params
+            .into_iter()
+            .map(|(k, v)| (k.as_ref().to_owned(), v.to_string()))
+            .collect();
+        let mut take = |param: &str| {
+            let index = params.iter().position(|(k, _)| k == param)?;
+            Some(params.remove(index).1)
+        };
+        let missing = |param: &str| UrlError::MissingParam {
+            route: name.to_owned(),
+            param: param.to_owned(),
+        };
+
+        let mut path = String::new();
+        for segment in segments {
+            let value = match segment {
+                Segment::Static(segment) => segment.to_owned(),
+                Segment::Param {
+                    name: param,
+                    constraint,
+                    optional,
+                } => match take(param) {
+                    Some(value) => {
+                        if constraint.as_ref().is_some_and(|c| !c.matches(&value)) {
+                            return Err(UrlError::InvalidParam {
+                                route: name.to_owned(),
+                                param: param.to_owned(),
+                                value,
+                            });
+                        }
+                        encode(&value).into_owned()
+                    }
+                    None if *optional => continue,
+                    None => return Err(missing(param)),
+                },
+                Segment::Wildcard => encode_path(&take("*").ok_or_else(|| missing("*"))?),
+                Segment::CatchAll(param) => encode_path(&take(param).unwrap_or_default()),
+            };
+            if !value.is_empty() {
+                path.push('/');
+                path.push_str(&value);
+            }
+        }
+        if path.is_empty() {
+            path.push('/');
+        }
+
+        if !params.is_empty() {
+            let query: Vec<String> = params
+                .iter()
+                .map(|(k, v)| format!("{}={}", encode(k), encode(v)))
+                .collect();
+            path.push('?');
+            path.push_str(&query.join("&"));
+        }
+        Ok(path)
+    }
+}
+
+/// Percent-encodes each segment of `path`, keeping the slashes between them.
+fn encode_path(path: &str) -> String {
+    path.split('/')
+        .filter(|s| !s.is_empty())
+        .map(|s| encode(s).into_owned())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn urls() -> UrlGenerator {
+        let mut urls = UrlGenerator::default();
+        urls.insert("home".into(), "");
+        urls.insert("user".into(), "/users/{id:int}");
+        urls.insert("tag".into(), "/tags/{slug}");
+        urls.insert("pages".into(), "/pages/{page?}");
+        urls.insert("files".into(), "/files/{*path}");
+        urls.insert("assets".into(), "/assets/*");
+        urls
+    }
+
+    const NO_PARAMS: [(&str, &str); 0] = [];
+
+    #[test]
+    #[should_panic(expected = "Route name `user` is used by more than one route")]
+    fn rejects_duplicate_names() {
+        let mut urls = urls();
+        urls.insert("user".into(), "/members/{id}");
+    }
+
+    #[test]
+    fn fills_and_encodes_params() {
+        let urls = urls();
+        assert_eq!(urls.url("home", NO_PARAMS).unwrap(), "/");
+        assert_eq!(urls.url("user", [("id", 42)]).unwrap(), "/users/42");
+        assert_eq!(
+            urls.url("tag", [("slug", "a b/c")]).unwrap(),
+            "/tags/a%20b%2Fc"
+        );
+    }
+
+    #[test]
+    fn appends_unused_params_as_query() {
+        let urls = urls();
+        assert_eq!(
+            urls.url("tag", [("slug", "rust"), ("q", "a&b")]).unwrap(),
+            "/tags/rust?q=a%26b"
+        );
+    }
+
+    #[test]
+    fn leaves_out_missing_optional_params() {
+        let urls = urls();
+        assert_eq!(urls.url("pages", NO_PARAMS).unwrap(), "/pages");
+        assert_eq!(urls.url("pages", [("page", 2)]).unwrap(), "/pages/2");
+    }
+
+    #[test]
+    fn keeps_slashes_of_the_rest_of_the_path() {
+        let urls = urls();
+        assert_eq!(
+            urls.url("files", [("path", "docs/a b.pdf")]).unwrap(),
+            "/files/docs/a%20b.pdf"
+        );
+        assert_eq!(urls.url("files", NO_PARAMS).unwrap(), "/files");
+        assert_eq!(
+            urls.url("assets", [("*", "css/app.css")]).unwrap(),
+            "/assets/css/app.css"
+        );
+    }
+
+    #[test]
+    fn reports_unusable_params() {
+        let urls = urls();
+        assert_eq!(
+            urls.url("missing", NO_PARAMS),
+            Err(UrlError::UnknownRoute("missing".into()))
+        );
+        assert_eq!(
+            urls.url("tag", NO_PARAMS),
+            Err(UrlError::MissingParam {
+                route: "tag".into(),
+                param: "slug".into(),
+            })
+        );
+        assert_eq!(
+            urls.url("user", [("id", "me")]),
+            Err(UrlError::InvalidParam {
+                route: "user".into(),
+                param: "id".into(),
+                value: "me".into(),
+            })
+        );
+        assert_eq!(
+            urls.url("assets", NO_PARAMS),
+            Err(UrlError::MissingParam {
+                route: "assets".into(),
+                param: "*".into(),
+            })
+        );
+    }
+}
@@ -0,0 +1,388 @@
+use std::{
+    io::{self, BufReader, Read, Write},
+    net::{Shutdown, TcpStream},
+    time::Duration,
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use sha1_smol::Sha1;
+
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+const OPCODE_CONTINUATION: u8 = 0x0;
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_BINARY: u8 = 0x2;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xA;
+
+pub const CLOSE_NORMAL: u16 = 1000;
+pub const CLOSE_PROTOCOL_ERROR: u16 = 1002;
+pub const CLOSE_INVALID_DATA: u16 = 1007;
+pub const CLOSE_TOO_LARGE: u16 = 1009;
+
+/// Computes the `Sec-WebSocket-Accept` value for a client's `Sec-WebSocket-Key`.
+pub(crate) fn accept_key(key: &str) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(key.trim().as_bytes());
+    hasher.update(GUID.as_bytes());
+    STANDARD.encode(hasher.digest().bytes())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    /// The close code and reason sent by the peer, if any.
+    Close(Option<(u16, String)>),
+}
+
+/// A WebSocket connection established through a `Router::websocket` route.
+///
+/// Incoming fragmented messages are reassembled, pings are answered automatically
+/// and a close frame from the client is echoed before it is returned.
+#[derive(Debug)]
+pub struct WebSocket {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    max_message_size: usize,
+    frame_size: Option<usize>,
+    fragments: Option<(u8, Vec<u8>)>,
+    closed: bool,
+}
+
+impl WebSocket {
+    pub(crate) fn new(stream: TcpStream) -> io::Result<WebSocket> {
+        // The keep-alive timeout of the HTTP connection does not apply to a socket
+        stream.set_read_timeout(None)?;
+        Ok(WebSocket {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+            max_message_size: 16 * 1024 * 1024,
+            frame_size: None,
+            fragments: None,
+            closed: false,
+        })
+    }
+
+    /// Sets the largest message accepted from the client, defaults to 16 MiB.
+    /// Bigger messages close the connection with status 1009.
+    pub fn set_max_message_size(&mut self, bytes: usize) {
+        self.max_message_size = bytes;
+    }
+
+    /// Splits outgoing text and binary messages into frames of at most `bytes`.
+    /// Messages are sent as a single frame by default.
+    pub fn set_frame_size(&mut self, bytes: Option<usize>) {
+        self.frame_size = bytes.filter(|size| *size > 0);
+    }
+
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.writer.set_read_timeout(timeout)
+    }
+
+    /// Waits for the next message from the client.
+    pub fn receive(&mut self) -> io::Result<Message> {
+        loop {
+            let (fin, opcode, payload) = self.read_frame()?;
+            match opcode {
+                OPCODE_PING => {
+                    self.write_frame(true, OPCODE_PONG, &payload)?;
+                    return Ok(Message::Ping(payload));
+                }
+                OPCODE_PONG => return Ok(Message::Pong(payload)),
+                OPCODE_CLOSE => {
+                    let close = parse_close(&payload);
+                    if !self.closed {
+                        let code = close.as_ref().map_or(CLOSE_NORMAL, |(code, _)| *code);
+                        self.close(code, "")?;
+                    }
+                    return Ok(Message::Close(close));
+                }
+                OPCODE_TEXT | OPCODE_BINARY if self.fragments.is_none() => {
+                    if fin {
+                        return self.message(opcode, payload);
+                    }
+                    self.fragments = Some((opcode, payload));
+                }
+                OPCODE_CONTINUATION if self.fragments.is_some() => {
+                    let (first_opcode, mut data) = self.fragments.take().unwrap();
+                    if data.len() + payload.len() > self.max_message_size {
+                        return Err(self.fail(CLOSE_TOO_LARGE, "Message too large"));
+                    }
+                    data.extend_from_slice(&payload);
+                    if fin {
+                        return self.message(first_opcode, data);
+                    }
+                    self.fragments = Some((first_opcode, data));
+                }
+                _ => return Err(self.fail(CLOSE_PROTOCOL_ERROR, "Unexpected frame")),
+            }
+        }
+    }
+
+    pub fn send(&mut self, message: Message) -> io::Result<()> {
+        match message {
+            Message::Text(text) => self.send_data(OPCODE_TEXT, text.as_bytes()),
+            Message::Binary(data) => self.send_data(OPCODE_BINARY, &data),
+            Message::Ping(data) => self.write_frame(true, OPCODE_PING, &data),
+            Message::Pong(data) => self.write_frame(true, OPCODE_PONG, &data),
+            Message::Close(close) => {
+                let (code, reason) = close.unwrap_or((CLOSE_NORMAL, String::new()));
+                self.close(code, reason)
+            }
+        }
+    }
+
+    pub fn send_text<T: AsRef<str>>(&mut self, text: T) -> io::Result<()> {
+        self.send_data(OPCODE_TEXT, text.as_ref().as_bytes())
+    }
+
+    pub fn send_binary<T: AsRef<[u8]>>(&mut self, data: T) -> io::Result<()> {
+        self.send_data(OPCODE_BINARY, data.as_ref())
+    }
+
+    pub fn ping<T: AsRef<[u8]>>(&mut self, data: T) -> io::Result<()> {
+        self.write_frame(true, OPCODE_PING, data.as_ref())
+    }
+
+    pub fn pong<T: AsRef<[u8]>>(&mut self, data: T) -> io::Result<()> {
+        self.write_frame(true, OPCODE_PONG, data.as_ref())
+    }
+
+    /// Sends a close frame. The client is expected to answer with its own close
+    /// frame, which `receive` returns as [`Message::Close`].
+    pub fn close<T: AsRef<str>>(&mut self, code: u16, reason: T) -> io::Result<()> {
+        if self.closed {
+            return Ok(());
+        }
+        self.closed = true;
+        let mut payload = code.to_be_bytes().to_vec();
+        payload.extend_from_slice(reason.as_ref().as_bytes());
+        // Control frame payloads are limited to 125 bytes
+        payload.truncate(125);
+        self.write_frame(true, OPCODE_CLOSE, &payload)
+    }
+
+    fn message(&mut self, opcode: u8, data: Vec<u8>) -> io::Result<Message> {
+        if opcode == OPCODE_BINARY {
+            return Ok(Message::Binary(data));
+        }
+        match String::from_utf8(data) {
+            Ok(text) => Ok(Message::Text(text)),
+            Err(_) => Err(self.fail(CLOSE_INVALID_DATA, "Text message is not valid UTF-8")),
+        }
+    }
+
+    /// Closes the connection with `code` and returns the matching error.
+    fn fail(&mut self, code: u16, reason: &str) -> io::Error {
+        let _ = self.close(code, reason);
+        io::Error::new(io::ErrorKind::InvalidData, reason)
+    }
+
+    fn send_data(&mut self, opcode: u8, data: &[u8]) -> io::Result<()> {
+        let frame_size = self.frame_size.unwrap_or(data.len()).max(1);
+        if data.len() <= frame_size {
+            return self.write_frame(true, opcode, data);
+        }
+        let mut chunks = data.chunks(frame_size).peekable();
+        let mut opcode = opcode;
+        while let Some(chunk) = chunks.next() {
+            self.write_frame(chunks.peek().is_none(), opcode, chunk)?;
+            opcode = OPCODE_CONTINUATION;
+        }
+        Ok(())
+    }
+
+    fn write_frame(&mut self, fin: bool, opcode: u8, payload: &[u8]) -> io::Result<()> {
+        let mut frame = Vec::with_capacity(payload.len() + 10);
+        frame.push(if fin { 0x80 } else { 0 } | opcode);
+        // Frames sent by the server are never masked
+        if payload.len() < 126 {
+            frame.push(payload.len() as u8);
+        } else if payload.len() <= u16::MAX as usize {
+            frame.push(126);
+            frame.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+        } else {
+            frame.push(127);
+            frame.extend_from_slice(&(payload.len() as u64).to_be_bytes());
+        }
+        frame.extend_from_slice(payload);
+        self.writer.write_all(&frame)?;
+        self.writer.flush()
+    }
+
+    fn read_frame(&mut self) -> io::Result<(bool, u8, Vec<u8>)> {
+        let mut head = [0; 2];
+        self.reader.read_exact(&mut head)?;
+        let fin = head[0] & 0x80 != 0;
+        let opcode = head[0] & 0x0F;
+        if head[0] & 0x70 != 0 {
+            return Err(self.fail(CLOSE_PROTOCOL_ERROR, "Reserved bits set"));
+        }
+        if head[1] & 0x80 == 0 {
+            return Err(self.fail(CLOSE_PROTOCOL_ERROR, "Client frames must be masked"));
+        }
+
+        let length = match head[1] & 0x7F {
+            126 => {
+                let mut length = [0; 2];
+                self.reader.read_exact(&mut length)?;
+                u16::from_be_bytes(length) as u64
+            }
+            127 => {
+                let mut length = [0; 8];
+                self.reader.read_exact(&mut length)?;
+                u64::from_be_bytes(length)
+            }
+            length => length as u64,
+        };
+        if opcode >= OPCODE_CLOSE && (!fin || length > 125) {
+            return Err(self.fail(CLOSE_PROTOCOL_ERROR, "Invalid control frame"));
+        }
+        if length > self.max_message_size as u64 {
+            return Err(self.fail(CLOSE_TOO_LARGE, "Message too large"));
+        }
+
+        let mut mask = [0; 4];
+        self.reader.read_exact(&mut mask)?;
+        let mut payload = vec![0; length as usize];
+        self.reader.read_exact(&mut payload)?;
+        for (i, byte) in payload.iter_mut().enumerate() {
+            *byte ^= mask[i % 4];
+        }
+        Ok((fin, opcode, payload))
+    }
+}
+
+fn parse_close(payload: &[u8]) -> Option<(u16, String)> {
+    if payload.len() < 2 {
+        return None;
+    }
+    let code = u16::from_be_bytes([payload[0], payload[1]]);
+    let reason = String::from_utf8_lossy(&payload[2..]).to_string();
+    Some((code, reason))
+}
+
+impl Drop for WebSocket {
+    fn drop(&mut self) {
+        let _ = self.close(CLOSE_NORMAL, "");
+        let _ = self.writer.shutdown(Shutdown::Both);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use super::*;
+
+    /// A server socket and the client end of its connection.
+    fn connect() -> (WebSocket, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        (WebSocket::new(server).unwrap(), client)
+    }
+
+    /// A masked client frame.
+    fn frame(fin: bool, opcode: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [0x37, 0xfa, 0x21, 0x3d];
+        let mut frame = vec![if fin { 0x80 } else { 0 } | opcode];
+        if payload.len() < 126 {
+            frame.push(0x80 | payload.len() as u8);
+        } else if payload.len() <= u16::MAX as usize {
+            frame.push(0x80 | 126);
+            frame.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+        } else {
+            frame.push(0x80 | 127);
+            frame.extend_from_slice(&(payload.len() as u64).to_be_bytes());
+        }
+        frame.extend_from_slice(&mask);
+        frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        frame
+    }
+
+    /// Reads the close code of the close frame sent to the client.
+    fn close_code(client: &mut TcpStream) -> u16 {
+        let mut head = [0; 4];
+        client.read_exact(&mut head).unwrap();
+        assert_eq!(head[0], 0x80 | OPCODE_CLOSE);
+        u16::from_be_bytes([head[2], head[3]])
+    }
+
+    #[test]
+    fn accept_key_matches_the_rfc_sample() {
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn unmasks_the_rfc_sample_frame() {
+        let (mut socket, mut client) = connect();
+        client
+            .write_all(&[
+                0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58,
+            ])
+            .unwrap();
+        assert_eq!(socket.receive().unwrap(), Message::Text("Hello".into()));
+    }
+
+    #[test]
+    fn reads_extended_payload_lengths() {
+        let (mut socket, mut client) = connect();
+        for length in [125, 126, 65535, 65536] {
+            let payload: Vec<u8> = (0..length).map(|i| i as u8).collect();
+            client
+                .write_all(&frame(true, OPCODE_BINARY, &payload))
+                .unwrap();
+            assert_eq!(socket.receive().unwrap(), Message::Binary(payload));
+        }
+    }
+
+    #[test]
+    fn reassembles_fragmented_messages() {
+        let (mut socket, mut client) = connect();
+        client
+            .write_all(&frame(false, OPCODE_TEXT, b"Hel"))
+            .unwrap();
+        client.write_all(&frame(true, OPCODE_PING, b"")).unwrap();
+        client
+            .write_all(&frame(true, OPCODE_CONTINUATION, b"lo"))
+            .unwrap();
+        assert_eq!(socket.receive().unwrap(), Message::Ping(vec![]));
+        assert_eq!(socket.receive().unwrap(), Message::Text("Hello".into()));
+    }
+
+    #[test]
+    fn rejects_unmasked_frames() {
+        let (mut socket, mut client) = connect();
+        client.write_all(&[0x81, 0x05]).unwrap();
+        client.write_all(b"Hello").unwrap();
+        assert!(socket.receive().is_err());
+        assert_eq!(close_code(&mut client), CLOSE_PROTOCOL_ERROR);
+    }
+
+    #[test]
+    fn rejects_messages_over_the_size_limit() {
+        let (mut socket, mut client) = connect();
+        socket.set_max_message_size(4);
+        client
+            .write_all(&frame(true, OPCODE_TEXT, b"Hello"))
+            .unwrap();
+        assert!(socket.receive().is_err());
+        assert_eq!(close_code(&mut client), CLOSE_TOO_LARGE);
+    }
+
+    #[test]
+    fn rejects_fragmented_control_frames() {
+        let (mut socket, mut client) = connect();
+        client.write_all(&frame(false, OPCODE_PING, b"")).unwrap();
+        assert!(socket.receive().is_err());
+        assert_eq!(close_code(&mut client), CLOSE_PROTOCOL_ERROR);
+    }
+}